@@ -1,5 +1,17 @@
 use crate::assembler_config::AssemblerConfig;
 use crate::assembler_error::AssemblerError;
+use crate::container::Container;
+use crate::encoding;
+use crate::encoding::Word;
+use crate::expression;
+use crate::instruction_validator::InstructionValidator;
+use crate::layout;
+use crate::layout::{PinnedRegion, Slot};
+use crate::output_format::OutputFormat;
+use crate::sexpr;
+use crate::simulator;
+use crate::simulator::Assertion;
+use batpu_assembly::assembly_error::AssemblyError;
 use batpu_assembly::components::address;
 use batpu_assembly::components::address::Address;
 use batpu_assembly::components::condition::Condition;
@@ -9,22 +21,127 @@ use batpu_assembly::components::offset::Offset;
 use batpu_assembly::components::register::Register;
 use batpu_assembly::instruction::{Instruction, BITS};
 use batpu_assembly::Labels;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::iter::Iterator;
+use std::num::{IntErrorKind, ParseIntError};
+use std::path::{Path, PathBuf};
 
 const CHARACTERS: &[char] = &[' ', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '.', '!', '?'];
 
+const OFFSET_MIN: i32 = -8;
+const OFFSET_MAX: i32 = 7;
+
+// Immediate::new and new_signed mask to 8 bits, so the range is checked before building one
+const IMMEDIATE_MIN: i32 = -128;
+const IMMEDIATE_MAX: i32 = 255;
+
+// Characters are written to the character display through this port
+const CHAR_DISP_PORT: i32 = 247;
+
+// Memory from this address up is mapped to I/O ports
+const PORTS_START: i32 = 240;
+
+#[derive(Clone)]
+enum Statement {
+    Instruction(Instruction),
+    Word(Word),
+    LabelByte(Instruction, LabelByte),
+    // A jump to a label plus a constant, such as "jmp loop+3", resolved once label addresses are final
+    LabelOffset(Instruction, i32)
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<String>
+}
+
+// lo(label) or hi(label), resolved once label addresses are final
+#[derive(Clone)]
+struct LabelByte {
+    label: String,
+    high: bool
+}
+
+// A file read with #include, with the file and line of the #include
+#[derive(Clone)]
+struct IncludedFile {
+    path: String,
+    lines: Vec<String>,
+    parent: (usize, u32)
+}
+
 pub struct Assembler {
     pub config: AssemblerConfig,
     
-    instructions: Vec<(Instruction, u32)>,
+    // Every statement with its line and file, file 0 is the input and file n is included_files[n - 1]
+    instructions: Vec<(Statement, u32, usize)>,
     labels: Labels,
     defines: HashMap<String, String>,
+    used_defines: HashSet<String>,
+    // Define each substituted argument of the current statement came from, by its value
+    piece_defines: HashMap<String, String>,
+    // Byte offset of each argument of the current statement within it, by its substituted value
+    argument_columns: Vec<(String, usize)>,
+    // Argument the getters last looked at, so errors can point at its column
+    argument: Option<String>,
+    // Set while parsing lines joined with a backslash, whose columns don't match any line in the file
+    joined_line: bool,
+    // Set when an expression reads the address of a label
+    read_label: bool,
+    // Line and file of every immediate or offset computed from a label address, which doesn't move along with the label
+    label_numbers: Vec<(u32, usize)>,
+    // Value, immediate, line and file of the last ldi into each register, for the character literal hint and stores to known addresses
+    loaded_immediates: HashMap<u32, (i32, String, u32, usize)>,
+    // Address and line of every str to an address known while assembling, checked against the program size at the end
+    constant_stores: Vec<(i32, u32, usize)>,
+    // Whether a define was first used by adi (true) or ldi (false), None once it was warned about
+    define_signedness: HashMap<String, Option<bool>>,
+    warnings: Vec<AssemblerError>,
+    // Kept apart from warnings, since they're never turned into errors
+    lints: Vec<AssemblerError>,
+    // Labels read by expressions, such as "table" in "ldi r1 table + 2"
+    used_labels: HashSet<String>,
+    // Line and file every label was defined on
+    label_lines: HashMap<String, (u32, usize)>,
 
+    pinned: Vec<PinnedRegion>,
+    pinned_labels: HashMap<String, usize>,
+    pin: Option<(usize, usize, u32)>,
+
+    macros: HashMap<String, Macro>,
+    // Name, definition and line of the macro whose body is being recorded
+    macro_definition: Option<(String, Macro, u32)>,
+    expanding_macros: Vec<String>,
+    // Defines made by each macro being expanded, which only last until the end of the expansion
+    macro_defines: Vec<Vec<String>>,
+    // Registers set aside with ".reserve", which code can't use
+    reserved_registers: HashSet<u32>,
+    // Pinned regions move code around, so they can't be mixed with .org
+    uses_org: bool,
+
+    validators: Vec<Box<dyn InstructionValidator>>,
+    assertions: Vec<Assertion>,
+
+    source_lines: Vec<String>,
+    // Trailing comments, by the file and line of the statements they belong to
+    comments: HashMap<(usize, u32), String>,
+    // Every statement after define substitution and macro expansion, when only preprocessing
+    expanded_source: Vec<String>,
+    // Canonical paths of the files being parsed, the innermost include last
+    files: Vec<PathBuf>,
+    included_files: Vec<IncludedFile>,
+    // File being parsed, 0 for the input
+    file: usize,
+    // Errors in included files, reported one by one instead of as an error of the #include line
+    included_errors: Vec<Box<dyn Error>>,
+    // Every label address found by the first pass, so expressions can refer to labels later in the file
+    first_pass_labels: Labels,
+    // Set while running the first pass, where expressions that can't be evaluated yet are read as 0
+    first_pass: bool,
     line: u32
 }
 
@@ -33,38 +150,13 @@ impl Assembler {
         let mut defines = HashMap::new();
 
         if config.default_defines {
-            // Screen
-
-            defines.insert("SCR_PIX_X".to_string(), "240".to_string());
-            defines.insert("SCR_PIX_Y".to_string(), "241".to_string());
-
-            defines.insert("SCR_DRAW_PIX".to_string(), "242".to_string());
-            defines.insert("SCR_CLR_PIX".to_string(), "243".to_string());
-            defines.insert("SCR_GET_PIX".to_string(), "244".to_string());
-
-            defines.insert("SCR_PUSH".to_string(), "245".to_string());
-            defines.insert("SCR_CLR".to_string(), "246".to_string());
-
-            // Character Display
-
-            defines.insert("CHAR_DISP_PUSH".to_string(), "247".to_string());
-
-            defines.insert("CHAR_DISP_DRAW".to_string(), "248".to_string());
-            defines.insert("CHAR_DISP_CLR".to_string(), "249".to_string());
-
-            // Number Display
-
-            defines.insert("NUM_DISP_SHOW".to_string(), "250".to_string());
-            defines.insert("NUM_DISP_CLR".to_string(), "251".to_string());
-
-            defines.insert("NUM_DISP_SIGNED".to_string(), "252".to_string());
-            defines.insert("NUM_DISP_UNSIGNED".to_string(), "253".to_string());
-
-            // Random Number Generator
-            defines.insert("RNG".to_string(), "254".to_string());
+            for &(name, value) in config.isa_revision.default_defines() {
+                defines.insert(name.to_string(), value.to_string());
+            }
+        }
 
-            // Controller
-            defines.insert("CONTROLLER".to_string(), "255".to_string());
+        if let Some(version) = config.build_version {
+            defines.insert("__VERSION__".to_string(), version.to_string());
         }
 
         Self {
@@ -73,14 +165,58 @@ impl Assembler {
             instructions: Vec::new(),
             labels: HashMap::new(),
             defines,
+            used_defines: HashSet::new(),
+            piece_defines: HashMap::new(),
+            argument_columns: Vec::new(),
+            argument: None,
+            joined_line: false,
+            read_label: false,
+            label_numbers: Vec::new(),
+            loaded_immediates: HashMap::new(),
+            constant_stores: Vec::new(),
+            define_signedness: HashMap::new(),
+            warnings: Vec::new(),
+            lints: Vec::new(),
+            used_labels: HashSet::new(),
+            label_lines: HashMap::new(),
+
+            pinned: Vec::new(),
+            pinned_labels: HashMap::new(),
+            pin: None,
 
+            macros: HashMap::new(),
+            macro_definition: None,
+            expanding_macros: Vec::new(),
+            macro_defines: Vec::new(),
+            reserved_registers: HashSet::new(),
+            uses_org: false,
+
+            validators: Vec::new(),
+            assertions: Vec::new(),
+
+            source_lines: Vec::new(),
+            comments: HashMap::new(),
+            expanded_source: Vec::new(),
+            files: Vec::new(),
+            included_files: Vec::new(),
+            file: 0,
+            included_errors: Vec::new(),
+            first_pass_labels: HashMap::new(),
+            first_pass: false,
             line: 0
         }
     }
 
-    fn check_arguments(&self, mut actual_len: usize, expected: &[&str]) -> Result<(), AssemblerError> {
+    fn check_arguments(&mut self, mut actual_len: usize, expected: &[&str]) -> Result<(), AssemblerError> {
         actual_len -= 1;
-        
+
+        // Such as annotations in generated code, the arguments that are used are still checked
+        if self.config.ignore_extra_arguments && actual_len > expected.len() {
+            let extra = actual_len - expected.len();
+            self.warn(format!("Ignoring {} extra argument{}", extra, if extra == 1 { "" } else { "s" }));
+            return Ok(());
+        }
+
         if actual_len != expected.len() {
             return Err(AssemblerError::new_line(format!(
                 "Expected {}, got {} instead",
@@ -101,11 +237,190 @@ impl Assembler {
         Ok(())
     }
 
-    fn parse_piece(&mut self, piece: &str) -> Result<Option<Instruction>, Box<dyn Error>> {
-        let mut args: Vec<&str> = piece
-            .split_whitespace()
+    pub fn from_instructions(instructions: Vec<Instruction>, labels: Labels) -> Result<Self, Vec<AssemblerError>> {
+        let mut errors = Vec::new();
+
+        for (address, instruction) in instructions.iter().enumerate() {
+            if let Some(label) = Self::label_reference(instruction) {
+                if !labels.contains_key(label) {
+                    errors.push(AssemblerError::new(format!("Label \"{}\" used at address {} was never defined", label, address)));
+                }
+            }
+        }
+
+        if instructions.len() > address::MAX_VALUE as usize {
+            errors.push(AssemblerError::new(format!("Program reached maximum size ({} instructions)", Self::with_commas(address::MAX_POSSIBLE_COUNT))));
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut assembler = Self::new(AssemblerConfig::default());
+
+        // Line 0 marks instructions that didn't come from source
+        assembler.instructions = instructions
+            .into_iter()
+            .map(|instruction| (Statement::Instruction(instruction), 0, 0))
+            .collect();
+        assembler.labels = labels;
+
+        Ok(assembler)
+    }
+
+    // The statements from a label up to the next one, as a program of its own with labels rebased to 0
+    pub fn region(&self, label: &str) -> Result<Self, Vec<AssemblerError>> {
+        let start = match self.labels.get(label) {
+            Some(&address) => address as usize,
+            None => return Err(vec![AssemblerError::new(format!("Label \"{}\" was never defined", label))])
+        };
+
+        let end = self.labels
+            .values()
+            .map(|&address| address as usize)
+            .filter(|&address| address > start)
+            .min()
+            .unwrap_or(self.instructions.len());
+
+        let labels: Labels = self.labels
+            .iter()
+            .filter(|&(_, &address)| (start..end).contains(&(address as usize)))
+            .map(|(name, &address)| (name.clone(), address - start as u32))
             .collect();
 
+        let mut errors = Vec::new();
+        for (statement, line, file) in &self.instructions[start..end] {
+            let reference = match statement {
+                Statement::Instruction(instruction) | Statement::LabelOffset(instruction, _) => Self::label_reference(instruction),
+                Statement::LabelByte(_, label_byte) => Some(label_byte.label.as_str()),
+                Statement::Word(_) => None
+            };
+
+            if let Some(reference) = reference {
+                if !labels.contains_key(reference) {
+                    errors.push(self.in_file(AssemblerError::new_line(format!("Label \"{}\" is outside of \"{}\"", reference, label), *line), *file));
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut assembler = Self::new(self.config.clone());
+        assembler.instructions = self.instructions[start..end].to_vec();
+        assembler.labels = labels;
+        assembler.source_lines = self.source_lines.clone();
+        assembler.comments = self.comments.clone();
+        assembler.included_files = self.included_files.clone();
+
+        Ok(assembler)
+    }
+
+    pub fn add_validator(&mut self, validator: Box<dyn InstructionValidator>) {
+        self.validators.push(validator);
+    }
+
+    // The comment after the statements on a line of the input, such as "Clear the screen" in "str r1 r2 // Clear the screen"
+    pub fn comment(&self, line: u32) -> Option<&str> {
+        self.comments.get(&(0, line)).map(|comment| comment.as_str())
+    }
+
+    // Label addresses are final once parse succeeds
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
+
+    pub fn defines(&self) -> &HashMap<String, String> {
+        &self.defines
+    }
+
+    // Such as constants given on the command line, added before parsing so the source can't define them again
+    pub fn add_define(&mut self, name: &str, value: &str) -> Result<(), AssemblerError> {
+        if self.defines.contains_key(name) {
+            return Err(AssemblerError::new(format!("Definition of \"{}\" already exists", name)));
+        }
+
+        self.defines.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    // Line in the input of the statement at address, None for code the assembler inserted or included from another file
+    pub fn source_line(&self, address: usize) -> Option<u32> {
+        self.instructions
+            .get(address)
+            .filter(|&&(_, line, file)| line != 0 && file == 0)
+            .map(|&(_, line, _)| line)
+    }
+
+    // Included file (None for the input), line and text of the statement at address, None for code the assembler inserted
+    pub fn source_text(&self, address: usize) -> Option<(Option<&str>, u32, &str)> {
+        let &(_, line, file) = self.instructions.get(address).filter(|&&(_, line, _)| line != 0)?;
+
+        let (path, lines) = match file {
+            0 => (None, &self.source_lines),
+            _ => {
+                let included = &self.included_files[file - 1];
+                (Some(included.path.as_str()), &included.lines)
+            }
+        };
+
+        Some((path, line, lines.get(line as usize - 1).map_or("", |text| text.as_str())))
+    }
+
+    // Points errors from included files at the file they're in
+    fn in_file(&self, error: AssemblerError, file: usize) -> AssemblerError {
+        if file == 0 || error.file.is_some() {
+            return error;
+        }
+
+        error.with_file(self.included_files[file - 1].path.clone())
+    }
+
+    // The source with every define substituted and macro expanded, one statement per line
+    pub fn expanded_source(&self) -> String {
+        let mut source = String::new();
+
+        for line in &self.expanded_source {
+            source.push_str(line);
+            source.push('\n');
+        }
+
+        source
+    }
+
+    pub fn assertions(&self) -> &[Assertion] {
+        &self.assertions
+    }
+
+    // Warnings and style lints together, by line
+    pub fn warnings(&self) -> Vec<&AssemblerError> {
+        let mut warnings: Vec<&AssemblerError> = self.warnings.iter().chain(&self.lints).collect();
+        warnings.sort();
+        warnings
+    }
+
+    fn warn(&mut self, description: String) {
+        let warning = self.in_file(AssemblerError::new_line(description, self.line), self.file);
+        self.warnings.push(warning);
+    }
+
+    fn lint(&mut self, description: String) {
+        let lint = self.in_file(AssemblerError::new_line(description, self.line), self.file);
+        self.lints.push(lint);
+    }
+
+    fn check_memory_allowed(&self, name: &str) -> Result<(), AssemblerError> {
+        if !self.config.allow_memory {
+            return Err(AssemblerError::new_line(format!("Memory instructions are disabled, \"{}\" can't be used", name), self.line));
+        }
+
+        Ok(())
+    }
+
+    fn parse_piece(&mut self, piece: &str) -> Result<Vec<Statement>, Box<dyn Error>> {
+        let args = Self::split_arguments(piece);
+
         let name = args[0];
 
         if name.ends_with(':') {
@@ -113,12 +428,118 @@ impl Assembler {
 
             let label_name = name[..name.len() - 1].to_string();
 
-            if self.labels.contains_key(&label_name) {
+            if label_name.is_empty() {
+                return Err(AssemblerError::new_line("Label name can't be empty".to_string(), self.line).into());
+            }
+
+            // Other characters could be read as operators, making the label impossible to reference
+            if let Some(invalid) = label_name.chars().find(|&c| !c.is_ascii_alphanumeric() && c != '_' && c != '.') {
+                return Err(AssemblerError::new_line(format!("Label \"{}\" contains '{}', only letters, digits, '_' and '.' are allowed", label_name, invalid), self.line).into());
+            }
+
+            let address = self.instructions.len() as u32;
+
+            if let Some(&existing_address) = self.labels.get(&label_name) {
+                // Duplicate labels at the same address (e.g. from generated code) are harmless
+                if self.config.merge_duplicate_labels && existing_address == address {
+                    return Ok(Vec::new());
+                }
+
                 return Err(AssemblerError::new_line(format!("Label \"{}\" was already defined", label_name), self.line).into());
             }
 
-            self.labels.insert(label_name, self.instructions.len() as u32);
-            return Ok(None);
+            if self.pin.is_some() {
+                self.pinned_labels.insert(label_name.clone(), self.pinned.len());
+            }
+
+            if self.config.preprocess_only {
+                self.expanded_source.push(name.to_string());
+            }
+
+            // Code can jump here with any register values
+            self.loaded_immediates.clear();
+
+            self.label_lines.insert(label_name.clone(), (self.line, self.file));
+            self.labels.insert(label_name, address);
+            return Ok(Vec::new());
+        }
+
+        // Handled before splitting into arguments, since the text can contain spaces
+        if name.eq(".string") {
+            let text = piece[name.len()..].trim();
+
+            let text = match text.strip_prefix('"').and_then(|text| text.strip_suffix('"')) {
+                Some(text) => text,
+                None => return Err(AssemblerError::new_line(format!("Text {} must be in quotes", text), self.line).into())
+            };
+
+            let mut statements = Vec::new();
+            for char in text.chars() {
+                statements.push(Statement::Word(self.get_character(char)? as Word));
+            }
+
+            if self.config.preprocess_only {
+                self.expanded_source.push(piece.to_string());
+            }
+
+            return Ok(statements);
+        }
+
+        if name.eq(".macro") {
+            if args.len() < 2 {
+                return Err(AssemblerError::new_line("Expected a macro name".to_string(), self.line).into());
+            }
+
+            let macro_name = args[1];
+
+            // Instruction names are case-insensitive, so "LDI" would be shadowed as well
+            if Self::instruction_name(macro_name).is_some() || self.macros.contains_key(macro_name) {
+                return Err(AssemblerError::new_line(format!("Macro \"{}\" already exists", macro_name), self.line).into());
+            }
+
+            let definition = Macro {
+                parameters: args[2..].iter().map(|parameter| parameter.to_string()).collect(),
+                body: Vec::new()
+            };
+
+            self.macro_definition = Some((macro_name.to_string(), definition, self.line));
+            return Ok(Vec::new());
+        }
+
+        if name.eq(".endmacro") {
+            return Err(AssemblerError::new_line("\".endmacro\" without \".macro\"".to_string(), self.line).into());
+        }
+
+        // The arguments of the expanded or included lines don't point into this one
+        if self.macros.contains_key(name) {
+            let result = self.expand_macro(name, &args[1..]);
+            self.argument = None;
+
+            result?;
+            return Ok(Vec::new());
+        }
+
+        if name.eq("#include") {
+            self.check_arguments(args.len(), &["Path"])?;
+
+            let result = self.include(args[1]);
+            self.argument = None;
+
+            result?;
+            return Ok(Vec::new());
+        }
+
+        if name.eq("#undef") {
+            self.check_arguments(args.len(), &["Name"])?;
+
+            let define_name = args[1];
+
+            if self.defines.remove(define_name).is_none() {
+                return Err(AssemblerError::new_line(format!("Definition of \"{}\" doesn't exist", define_name), self.line).into());
+            }
+
+            self.define_signedness.remove(define_name);
+            return Ok(Vec::new());
         }
 
         if name.eq("#define") {
@@ -132,18 +553,172 @@ impl Assembler {
 
             let define_value = args[2];
 
+            if let Some(scope) = self.macro_defines.last_mut() {
+                scope.push(define_name.to_string());
+            }
+
             self.defines.insert(define_name.to_string(), define_value.to_string());
-            return Ok(None);
+            return Ok(Vec::new());
         }
 
-        for i in 0..args.len() {
-            let result = self.defines.get(args[i]);
-            if let Some(definition) = result {
-                args[i] = definition;
-            }
+        // Mnemonics are case-insensitive, "LDI r1 5" is "ldi r1 5"
+        let lowercase;
+        let name = match Self::instruction_name(name) {
+            Some(instruction_name) => {
+                lowercase = instruction_name;
+                lowercase.as_str()
+            },
+            None => name
+        };
+
+        let sources = args.clone();
+
+        self.piece_defines.clear();
+        self.argument_columns.clear();
+
+        let mut substituted: Vec<String> = Vec::new();
+        for &arg in &args {
+            let value = match self.resolve_define(arg)? {
+                Some(definition) => {
+                    self.piece_defines.insert(definition.clone(), arg.to_string());
+                    definition
+                },
+                None => arg.to_string()
+            };
+
+            self.argument_columns.push((value.clone(), Self::offset_in(piece, arg)));
+            substituted.push(value);
+        }
+
+        if self.config.preprocess_only {
+            self.expanded_source.push(substituted.join(" "));
+        }
+
+        let args: Vec<&str> = substituted
+            .iter()
+            .map(|arg| arg.as_str())
+            .collect();
+
+        if self.config.warnings && (name == "ldi" || name == "adi") && args.len() == 3 && sources[2] != args[2] {
+            self.check_define_signedness(sources[2], args[2], name == "adi");
         }
 
         let instruction = match name {
+            ".byte" => {
+                return self.parse_data(&args, 8);
+            },
+            ".2byte" | ".short" | ".word" | ".data" => {
+                return self.parse_data(&args, 16);
+            },
+            ".include_bin" => {
+                self.check_arguments(args.len(), &["Path"])?;
+                return self.include_binary(args[1]);
+            },
+            ".org" => {
+                if args.len() != 2 && args.len() != 3 {
+                    return Err(AssemblerError::new_line(format!("Expected Address and optional Fill, got {} arguments instead", args.len() - 1), self.line).into());
+                }
+
+                if self.pin.is_some() || !self.pinned.is_empty() {
+                    return Err(AssemblerError::new_line("\".org\" can't be used together with \".pin\"".to_string(), self.line).into());
+                }
+
+                // The nops would move the code after it away from the address
+                if self.config.interleave_nops {
+                    return Err(AssemblerError::new_line("\".org\" can't be used together with --interleave-nops".to_string(), self.line).into());
+                }
+
+                let address = match Self::parse_u32(args[1]) {
+                    Ok(address) if address <= address::MAX_VALUE => address as usize,
+                    Ok(_) => return Err(AssemblerError::new_line(format!("Address {} is past the end of the program (maximum is {})", args[1], address::MAX_VALUE), self.line).into()),
+                    Err(error) => return Err(self.literal_error("address", args[1], error, address::MAX_VALUE as i64))
+                };
+
+                let current_address = self.instructions.len();
+                if current_address > address {
+                    return Err(AssemblerError::new_line(format!("Can't move back to address {}, already at address {}", address, current_address), self.line).into());
+                }
+
+                self.uses_org = true;
+
+                // Padded with nop, or the given fill word
+                let fill = match args.get(2) {
+                    Some(fill) => Some(self.get_data(fill, 16)?),
+                    None => None
+                };
+
+                // Pushed with line 0 like other code the assembler inserts, so the padding isn't reported as unreachable
+                for _ in current_address..address {
+                    let statement = match fill {
+                        Some(word) => Statement::Word(word),
+                        None => Statement::Instruction(Instruction::NoOperation)
+                    };
+
+                    self.instructions.push((statement, 0, 0));
+                }
+
+                return Ok(Vec::new());
+            },
+            ".pin" => {
+                self.check_arguments(args.len(), &["Address"])?;
+
+                if self.uses_org {
+                    return Err(AssemblerError::new_line("\".pin\" can't be used together with \".org\"".to_string(), self.line).into());
+                }
+
+                if self.config.interleave_nops {
+                    return Err(AssemblerError::new_line("\".pin\" can't be used together with --interleave-nops".to_string(), self.line).into());
+                }
+
+                if let Some((address, _, _)) = self.pin {
+                    return Err(AssemblerError::new_line(format!("Pinned region at address {} is missing \".endpin\"", address), self.line).into());
+                }
+
+                // Code past the reach of jumps could never run, and the jump over it would be masked
+                let max_address = (encoding::ADDRESS_MAX as u32).min(address::MAX_VALUE);
+                let address = match Self::parse_u32(args[1]) {
+                    Ok(address) if address <= max_address => address as usize,
+                    Ok(_) => return Err(AssemblerError::new_line(format!("Pinned address {} is past the last address jumps can reach ({})", args[1], max_address), self.line).into()),
+                    Err(error) => return Err(self.literal_error("address", args[1], error, max_address as i64))
+                };
+
+                self.pin = Some((address, self.instructions.len(), self.line));
+                return Ok(Vec::new());
+            },
+            ".reserve" => {
+                if args.len() < 2 {
+                    return Err(AssemblerError::new_line("Expected at least one register to reserve".to_string(), self.line).into());
+                }
+
+                for &register in &args[1..] {
+                    match Self::register_index(register) {
+                        Some(index) if Register::new(index).is_ok() => {
+                            self.reserved_registers.insert(index);
+                        },
+                        _ => return Err(self.register_error(register, format!("Register \"{}\" is invalid", register)))
+                    }
+                }
+
+                return Ok(Vec::new());
+            },
+            ".endpin" => {
+                self.check_arguments(args.len(), &[])?;
+
+                match self.pin.take() {
+                    Some((address, start, line)) => {
+                        self.pinned.push(PinnedRegion {
+                            address,
+                            statements: start..self.instructions.len(),
+                            line
+                        });
+                    },
+                    None => {
+                        return Err(AssemblerError::new_line("\".endpin\" without \".pin\"".to_string(), self.line).into());
+                    }
+                }
+
+                return Ok(Vec::new());
+            },
             "nop" => {
                 self.check_arguments(args.len(), &[])?;
                 Instruction::NoOperation
@@ -162,11 +737,18 @@ impl Assembler {
             },
             "sub" => {
                 self.check_arguments(args.len(), &["RegA", "RegB", "RegC"])?;
-                Instruction::Subtraction(
+                let instruction = Instruction::Subtraction(
                     self.get_register(args[1])?,
                     self.get_register(args[2])?,
                     self.get_register(args[3])?
-                )
+                );
+
+                // Unlike "xor rX rX rX", this is rarely used on purpose to clear a register
+                if self.config.warnings && Self::register_index(args[1]) == Self::register_index(args[2]) {
+                    self.warn(format!("Subtracting \"{}\" from itself always results in 0", args[1]));
+                }
+
+                instruction
             },
             "nor" => {
                 self.check_arguments(args.len(), &["RegA", "RegB", "RegC"])?;
@@ -201,6 +783,12 @@ impl Assembler {
             },
             "ldi" => {
                 self.check_arguments(args.len(), &["RegA", "Immediate"])?;
+                if let Some(label_byte) = Self::get_label_byte(args[2]) {
+                    let instruction = Instruction::LoadImmediate(self.get_register(args[1])?, Immediate::new(0));
+                    self.forget_loaded_immediate(args[1]);
+                    return Ok(vec![Statement::LabelByte(instruction, label_byte)]);
+                }
+
                 Instruction::LoadImmediate(
                     self.get_register(args[1])?,
                     self.get_immediate(args[2])?
@@ -208,6 +796,12 @@ impl Assembler {
             },
             "adi" => {
                 self.check_arguments(args.len(), &["RegA", "Immediate"])?;
+                if let Some(label_byte) = Self::get_label_byte(args[2]) {
+                    let instruction = Instruction::AddImmediate(self.get_register(args[1])?, Immediate::new(0));
+                    self.forget_loaded_immediate(args[1]);
+                    return Ok(vec![Statement::LabelByte(instruction, label_byte)]);
+                }
+
                 Instruction::AddImmediate(
                     self.get_register(args[1])?,
                     self.get_immediate(args[2])?
@@ -215,19 +809,50 @@ impl Assembler {
             },
             "jmp" => {
                 self.check_arguments(args.len(), &["Label/Address"])?;
+                if let Some((label, offset)) = self.get_label_offset(args[1]) {
+                    return Ok(vec![Statement::LabelOffset(Instruction::Jump(Location::Label(label)), offset)]);
+                }
+
                 Instruction::Jump(
                     self.get_location(args[1])?
                 )
             },
             "brh" => {
                 self.check_arguments(args.len(), &["Condition", "Label/Address"])?;
+                if let Some((label, offset)) = self.get_label_offset(args[2]) {
+                    let instruction = Instruction::Branch(self.get_condition(args[1])?, Location::Label(label));
+                    return Ok(vec![Statement::LabelOffset(instruction, offset)]);
+                }
+
                 Instruction::Branch(
                     self.get_condition(args[1])?,
                     self.get_location(args[2])?
                 )
             },
+            "jz" | "jnz" | "jc" | "jnc" => {
+                self.check_arguments(args.len(), &["Label/Address"])?;
+                let condition = match name {
+                    "jz" => Condition::Zero,
+                    "jnz" => Condition::NotZero,
+                    "jc" => Condition::Carry,
+                    _ => Condition::NotCarry
+                };
+
+                if let Some((label, offset)) = self.get_label_offset(args[1]) {
+                    return Ok(vec![Statement::LabelOffset(Instruction::Branch(condition, Location::Label(label)), offset)]);
+                }
+
+                Instruction::Branch(
+                    condition,
+                    self.get_location(args[1])?
+                )
+            },
             "cal" => {
                 self.check_arguments(args.len(), &["Label/Address"])?;
+                if let Some((label, offset)) = self.get_label_offset(args[1]) {
+                    return Ok(vec![Statement::LabelOffset(Instruction::Call(Location::Label(label)), offset)]);
+                }
+
                 Instruction::Call(
                     self.get_location(args[1])?
                 )
@@ -237,19 +862,21 @@ impl Assembler {
                 Instruction::Return
             },
             "lod" => {
+                self.check_memory_allowed(name)?;
                 self.check_arguments(args.len(), &["RegA", "RegB", "Offset"])?;
                 Instruction::MemoryLoad(
                     self.get_register(args[1])?,
                     self.get_register(args[2])?,
-                    self.get_offset(args[3])?
+                    self.get_offset(args[3], sources[3])?
                 )
             },
             "str" => {
+                self.check_memory_allowed(name)?;
                 self.check_arguments(args.len(), &["RegA", "RegB", "Offset"])?;
                 Instruction::MemoryStore(
                     self.get_register(args[1])?,
                     self.get_register(args[2])?,
-                    self.get_offset(args[3])?
+                    self.get_offset(args[3], sources[3])?
                 )
             },
             "cmp" => {
@@ -307,376 +934,3792 @@ impl Assembler {
                     self.get_register(args[2])?
                 )
             },
+            "or" | "nand" => {
+                self.check_arguments(args.len(), &["RegA", "RegB", "RegC"])?;
+                let a = self.get_register(args[1])?;
+                let b = self.get_register(args[2])?;
+                let c = self.get_register(args[3])?;
+
+                // Two instructions, the second one inverts the result like "not"
+                let first = if name == "or" {
+                    Instruction::BitwiseNOR(a, b, c.clone())
+                } else {
+                    Instruction::BitwiseAND(a, b, c.clone())
+                };
+
+                if self.config.warnings {
+                    self.record_constant_store(name, &args);
+                    self.check_character_hint(name, &args);
+                    self.check_pseudo_r0(name, &args);
+                    self.check_r0_write(name, &args);
+                }
+
+                return Ok(vec![
+                    Statement::Instruction(first),
+                    Statement::Instruction(Instruction::BitwiseNOR(c.clone(), Register::new(0)?, c))
+                ]);
+            },
             _ => {
                 return Err(AssemblerError::new_line(format!("Unknown opcode: {}", name), self.line).into());
             }
         };
 
-        Ok(Some(instruction))
-    }
+        if self.config.warnings {
+            self.record_constant_store(name, &args);
+            self.check_character_hint(name, &args);
+            self.check_pseudo_r0(name, &args);
+            self.check_r0_write(name, &args);
+        }
 
-    fn parse_line(&mut self, mut line: &str) -> Result<Vec<(Instruction, u32)>, Vec<Box<dyn Error>>> {
-        let mut errors = Vec::new();
-        let mut instructions = Vec::new();
+        Ok(vec![Statement::Instruction(instruction)])
+    }
 
-        let comment_index = line.find("//");
+    // Other instructions into r0 still set the flags, such as "sub r1 r2 r0" for "cmp",
+    // but the flags of "or" and "nand" come from inverting r0 afterwards, which always reads as 0
+    fn check_r0_write(&mut self, name: &str, args: &[&str]) {
+        let destination = match name {
+            "ldi" => args.get(1),
+            "lod" => args.get(2),
+            "or" | "nand" => args.get(3),
+            _ => return
+        };
 
-        match comment_index {
-            Some(index) => {
-                line = &line[..index];
-            }
-            None => {}
+        if destination.and_then(|register| Self::register_index(register)) == Some(0) {
+            self.warn(format!("\"{}\" into r0 does nothing, since writes to r0 are discarded", name));
         }
+    }
 
-        line = line.trim();
+    // These pseudo-instructions already read r0 as 0, so r0 as an operand makes them useless
+    fn check_pseudo_r0(&mut self, name: &str, args: &[&str]) {
+        let result = match name {
+            "mov" => "0",
+            "lsh" => "0",
+            "not" => "255",
+            "neg" => "0",
+            _ => return
+        };
 
-        if line.is_empty() {
-            return Ok(instructions);
+        if Self::register_index(args[1]) == Some(0) {
+            self.warn(format!("\"{}\" of r0 always results in {}", name, result));
         }
 
-        let pieces: Vec<&str> = line
-            .split(';')
-            .map(|piece| piece.trim())
-            .collect();
+        if Self::register_index(args[2]) == Some(0) {
+            self.warn(format!("\"{}\" into r0 does nothing, since writes to r0 are discarded", name));
+        }
+    }
 
-        for piece in pieces {
-            if piece.is_empty() {
-                errors.push(AssemblerError::new_line("Useless semicolon".to_string(), self.line).into());
-            }
-            
-            let result = self.parse_piece(piece);
-            match result {
-                Ok(instruction) => {
-                    if let Some(instruction) = instruction {
-                        instructions.push((instruction, self.line));
-                    }
-                },
-                Err(error) => {
-                    errors.push(error);
+    fn check_define_signedness(&mut self, define: &str, value: &str, add: bool) {
+        // Only values from 128 to 255 (or -128 to -1) read differently as signed and unsigned
+        let value = match Self::parse_i32(value) {
+            Ok(value) if (128..=255).contains(&value) || (-128..=-1).contains(&value) => value as u8,
+            _ => return
+        };
+
+        match self.define_signedness.get(define) {
+            None => {
+                self.define_signedness.insert(define.to_string(), Some(add));
+            },
+            Some(&Some(first_add)) if first_add != add => {
+                self.define_signedness.insert(define.to_string(), None);
+                self.warn(format!(
+                    "Define \"{}\" is used by both ldi and adi, which read it as {} and {}",
+                    define,
+                    value,
+                    value as i8
+                ));
+            },
+            _ => {}
+        }
+    }
+
+    fn resolve_define(&mut self, name: &str) -> Result<Option<String>, AssemblerError> {
+        let mut value = match self.defines.get(name) {
+            Some(value) => value,
+            None => return Ok(None)
+        };
+
+        // Definitions can refer to other definitions, as long as they don't loop back
+        let mut chain = vec![name];
+        while let Some(next_value) = self.defines.get(value) {
+            if chain.contains(&value.as_str()) {
+                if chain.len() == 1 {
+                    return Err(AssemblerError::new_line(format!("Definition of \"{}\" refers to itself", name), self.line));
                 }
+
+                return Err(AssemblerError::new_line(format!("Definitions form a cycle: {} -> {}", chain.join(" -> "), value), self.line));
             }
+
+            chain.push(value);
+            value = next_value;
         }
 
-        if !errors.is_empty() {
-            return Err(errors);
+        for &used in &chain {
+            self.used_defines.insert(used.to_string());
         }
 
-        Ok(instructions)
+        Ok(Some(value.clone()))
     }
 
-    pub fn parse(&mut self, input: &str) -> Result<(), Vec<Box<dyn Error>>> {
-        let mut errors: Vec<Box<dyn Error>> = Vec::new();
+    // Bytes are packed into words in the byte order of the binary output, so they come out unchanged
+    fn include_binary(&self, path: &str) -> Result<Vec<Statement>, Box<dyn Error>> {
+        let (path, canonical_path) = self.resolve_path(path)?;
 
-        for (i, line) in input.lines().into_iter().enumerate() {
-            self.line = i as u32 + 1;
-            
-            let result = self.parse_line(line);
+        let bytes = match fs::read(&canonical_path) {
+            Ok(bytes) => bytes,
+            Err(error) => return Err(AssemblerError::new_line(format!("Failed to include \"{}\": {}", path, error), self.line).into())
+        };
 
-            match result {
-                Ok(mut result) => {
-                    self.instructions.append(&mut result);
-                },
-                Err(mut parse_errors) => {
-                    errors.append(&mut parse_errors);
-                }
-            }
+        let word_size = size_of::<Word>();
+        if bytes.len() % word_size != 0 {
+            return Err(AssemblerError::new_line(format!("\"{}\" is {} bytes long, which isn't a multiple of {}", path, bytes.len(), word_size), self.line).into());
         }
 
-        if self.instructions.len() > address::MAX_VALUE as usize {
-            errors.push(AssemblerError::new(format!("Program reached maximum size ({} instructions)", Self::with_commas(address::MAX_POSSIBLE_COUNT))).into());
-            return Err(errors);
-        }
+        let statements = bytes
+            .chunks(word_size)
+            .map(|chunk| {
+                let bytes = chunk.try_into().unwrap();
+                Statement::Word(if self.config.little_endian { Word::from_le_bytes(bytes) } else { Word::from_be_bytes(bytes) })
+            })
+            .collect();
 
-        if !errors.is_empty() {
-            return Err(errors);
+        Ok(statements)
+    }
+
+    fn record_macro_line(&mut self, line: &str) -> Result<(), Vec<Box<dyn Error>>> {
+        let code = match Self::comment_index(line) {
+            Some(index) => &line[..index],
+            None => line
+        };
+
+        match code.split_whitespace().next() {
+            Some(".endmacro") => {
+                let (name, definition, _) = self.macro_definition.take().unwrap();
+                self.macros.insert(name, definition);
+            },
+            Some(".macro") => {
+                return Err(vec![AssemblerError::new_line("Macros can't be defined inside other macros".to_string(), self.line).into()]);
+            },
+            _ => {
+                if let Some((_, definition, _)) = &mut self.macro_definition {
+                    definition.body.push(line.to_string());
+                }
+            }
         }
 
         Ok(())
     }
 
-    pub fn parse_file(&mut self, path: &str) -> Result<(), Vec<Box<dyn Error>>> {
-        let result = fs::read_to_string(path);
-
-        match result {
-            Ok(file) => self.parse(file.as_str()),
-            Err(error) => Err(vec![Box::new(error)])
+    fn expand_macro(&mut self, name: &str, arguments: &[&str]) -> Result<(), AssemblerError> {
+        if self.expanding_macros.iter().any(|expanding| expanding == name) {
+            return Err(AssemblerError::new_line(format!("Macro \"{}\" can't invoke itself", name), self.line));
         }
-    }
 
-    pub fn assemble(&self) -> Result<Vec<u16>, Vec<AssemblerError>> {
-        let mut errors: Vec<AssemblerError> = Vec::new();
+        let definition = &self.macros[name];
 
-        let binary = self.instructions
+        if arguments.len() != definition.parameters.len() {
+            return Err(AssemblerError::new_line(format!(
+                "Macro \"{}\" expects {} argument{}, got {} instead",
+                name,
+                definition.parameters.len(),
+                if definition.parameters.len() == 1 { "" } else { "s" },
+                arguments.len()
+            ), self.line));
+        }
+
+        let lines: Vec<String> = definition.body
             .iter()
-            .enumerate()
-            .map(|(address, (instruction, line))| {
-                let result = instruction.binary(address as u32, &self.labels);
-                match result {
-                    Ok(binary) => binary,
-                    Err(error) => {
-                        errors.push(AssemblerError::from_assembly_error_line(&error, *line));
-                        0
-                    }
-                }
-            })
+            .map(|line| Self::substitute_parameters(line, &definition.parameters, arguments))
             .collect();
 
-        if !errors.is_empty() {
-            return Err(errors);
+        // Every expanded line keeps the line number of the invocation
+        self.expanding_macros.push(name.to_string());
+        self.macro_defines.push(Vec::new());
+
+        let mut errors = Vec::new();
+        for line in lines {
+            if let Err(mut line_errors) = self.parse_line(&line) {
+                errors.append(&mut line_errors);
+            }
         }
 
-        if self.config.print_info {
-            println!(
-                "{} out of {} instructions used ({:.1}%)",
-                Self::with_commas(self.instructions.len() as u32),
-                Self::with_commas(address::MAX_POSSIBLE_COUNT),
-                self.instructions.len() as f32 * 100.0 / address::MAX_POSSIBLE_COUNT as f32
-            );
+        self.expanding_macros.pop();
+
+        // So the macro can be used again, with the same defines made for different arguments
+        for define in self.macro_defines.pop().unwrap_or_default() {
+            self.defines.remove(&define);
+            self.define_signedness.remove(&define);
         }
-        
-        Ok(binary)
+
+        if !errors.is_empty() {
+            let descriptions: Vec<String> = errors
+                .iter()
+                .map(|error| error.to_string())
+                .collect();
+
+            return Err(AssemblerError::new_line(format!("In macro \"{}\":\n{}", name, descriptions.join("\n")), self.line));
+        }
+
+        Ok(())
     }
-    
-    pub fn assemble_to_file(&mut self, path: &str) -> Result<(), Vec<Box<dyn Error>>> {
-        let assemble_result = self.assemble();
-        match assemble_result {
-            Ok(machine_code) => {
-                let file_result = File::create(path);
-                match file_result {
-                    Ok(file) => {
-                        let mut output_writer = BufWriter::new(file);
-
-                        if self.config.text_output {
-                            for (i, &instruction) in machine_code.iter().enumerate() {
-                                let line = format!("{:0bits$b}", instruction, bits=BITS as usize);
-
-                                let instruction_write = output_writer.write_all(line.as_bytes());
-                                if let Err(error) = instruction_write {
-                                    return Err(vec![error.into()]);
-                                }
-
-                                if i < machine_code.len() - 1 {
-                                    let line_write = output_writer.write_all(&[b'\n']);
-                                    if let Err(error) = line_write {
-                                        return Err(vec![error.into()]);
-                                    }
-                                }
-                            }
-                        } else {
-                            for &instruction in &machine_code {
-                                let bytes = instruction.to_be_bytes();
-
-                                let instruction_write = output_writer.write_all(&bytes);
-                                if let Err(error) = instruction_write {
-                                    return Err(vec![error.into()]);
-                                }
-                            }
-                        }
 
-                        Ok(())
-                    },
-                    Err(error) => {
-                        Err(vec![error.into()])
+    // Replaces every word that matches a parameter, such as "value" in "ldi r1 value; adi r1 1"
+    // Character and string literals and comments are copied as they are, so a parameter "A" leaves 'A' alone
+    fn substitute_parameters(line: &str, parameters: &[String], arguments: &[&str]) -> String {
+        let (code, comment) = line.split_at(Self::comment_index(line).unwrap_or(line.len()));
+
+        let mut substituted = String::new();
+        let mut word = String::new();
+        let mut open: Option<char> = None;
+        let mut escaped = false;
+
+        for char in code.chars() {
+            match open {
+                Some(_) if escaped => escaped = false,
+                Some(_) if char == '\\' => escaped = true,
+                Some(quote) if char == quote => open = None,
+                Some(_) => {},
+                None if char.is_ascii_alphanumeric() || char == '_' => {
+                    word.push(char);
+                    continue;
+                },
+                None => {
+                    Self::push_substituted_word(&mut substituted, &word, parameters, arguments);
+                    word.clear();
+
+                    if char == '\'' || char == '"' {
+                        open = Some(char);
                     }
                 }
-            },
-            Err(errors) => {
-                let errors = errors
-                    .iter()
-                    .map(|error| error.clone().into())
-                    .collect();
-                
-                Err(errors)
             }
+
+            substituted.push(char);
         }
-    }
 
-    fn parse_u32(str: &str) -> Result<u32, Box<dyn Error>> {
-        let str = str.replace('_', "");
+        Self::push_substituted_word(&mut substituted, &word, parameters, arguments);
+        substituted.push_str(comment);
+        substituted
+    }
 
-        if str.starts_with("0x") {
-            Ok(u32::from_str_radix(&str[2..], 16)?)
-        } else if str.starts_with("0b") {
-            Ok(u32::from_str_radix(&str[2..], 2)?)
-        } else {
-            Ok(str.parse()?)
+    fn push_substituted_word(substituted: &mut String, word: &str, parameters: &[String], arguments: &[&str]) {
+        match parameters.iter().position(|parameter| parameter == word) {
+            Some(index) => substituted.push_str(arguments[index]),
+            None => substituted.push_str(word)
         }
     }
 
-    fn parse_i32(str: &str) -> Result<i32, Box<dyn Error>> {
-        let str = str.replace('_', "");
+    fn parse_data(&self, args: &[&str], bits: u32) -> Result<Vec<Statement>, Box<dyn Error>> {
+        if args.len() < 2 {
+            return Err(AssemblerError::new_line(format!("Expected at least one value for \"{}\"", args[0]), self.line).into());
+        }
 
-        if str.starts_with("0x") {
-            Ok(i32::from_str_radix(&str[2..], 16)?)
-        } else if str.starts_with("0b") {
-            Ok(i32::from_str_radix(&str[2..], 2)?)
-        } else {
-            Ok(str.parse()?)
+        let mut statements = Vec::new();
+        for value in &args[1..] {
+            statements.push(Statement::Word(self.get_data(value, bits)?));
         }
+
+        Ok(statements)
     }
 
-    fn get_register(&self, register: &str) -> Result<Register, Box<dyn Error>> {
-        if !register.starts_with('r') {
-            return Err(AssemblerError::new_line(format!("Register \"{}\" must start with a lowercase 'r'", register), self.line).into());
+    fn parse_line(&mut self, line: &str) -> Result<(), Vec<Box<dyn Error>>> {
+        // lines() already splits on "\r\n", but a stray '\r' (such as from mixed line endings) mustn't end up in an argument
+        let line = line.trim_end_matches('\r');
+
+        // Other whitespace, such as non-breaking spaces in code copied from docs, is read as a regular space
+        let normalized;
+        let rewritten = line.chars().any(|c| c.is_whitespace() && !c.is_ascii());
+        let mut line = if rewritten {
+            normalized = line
+                .chars()
+                .map(|c| if c.is_whitespace() { ' ' } else { c })
+                .collect::<String>();
+
+            normalized.as_str()
+        } else {
+            line
+        };
+
+        if self.macro_definition.is_some() {
+            return self.record_macro_line(line);
         }
 
-        let register = &register[1..];
-        let result = register.parse::<u32>();
+        let source_line = line;
 
-        match result {
-            Ok(num) => {
-                let result = Register::new(num);
-                match result {
-                    Ok(register) => Ok(register),
-                    Err(error) => {
-                        Err(AssemblerError::from_assembly_error_line(&error, self.line).into())
+        // Columns are only given when they point at the line as it's written in the file
+        let exact_columns = !rewritten && !self.joined_line && self.expanding_macros.is_empty();
+
+        let mut errors: Vec<Box<dyn Error>> = Vec::new();
+
+        let comment_index = Self::comment_index(line);
+
+        match comment_index {
+            Some(index) => {
+                if let Some(assertion) = line[index + 2..].trim().strip_prefix("@assert") {
+                    match self.parse_assertion(assertion) {
+                        Ok(assertion) => self.assertions.push(assertion),
+                        Err(error) => errors.push(error.into())
                     }
                 }
-            },
-            Err(error) => {
-                Err(AssemblerError::new_line(format!("Failed to parse register \"{}\": {}", register, error), self.line).into())
+
+                let comment = line[index + 2..].trim();
+                if !comment.is_empty() && !line[..index].trim().is_empty() {
+                    self.comments.insert((self.file, self.line), comment.to_string());
+                }
+
+                line = &line[..index];
             }
+            None => {}
         }
-    }
 
-    fn get_immediate(&self, immediate: &str) -> Result<Immediate, Box<dyn Error>> {
-        if immediate.starts_with("'") {
-            if !immediate.ends_with("'") {
-                return Err(AssemblerError::new_line(format!("Immediate \"{}\" must end with ''", immediate), self.line).into());
+        line = line.trim();
+
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let pieces: Vec<&str> = Self::split_unquoted(line, |c| c == ';')
+            .into_iter()
+            .map(|piece| piece.trim())
+            .collect();
+
+        for piece in pieces {
+            if piece.is_empty() {
+                errors.push(AssemblerError::new_line("Useless semicolon".to_string(), self.line).into());
+                continue;
             }
 
-            let immediate = &immediate[1..immediate.len() - 1];
+            if let Some(index) = Self::unterminated_quote(piece) {
+                let mut error = AssemblerError::new_line("Unterminated character/string literal".to_string(), self.line);
+                if exact_columns {
+                    error = error.with_column(Self::offset_in(source_line, piece) + index + 1);
+                }
 
-            if immediate.len() != 1 {
-                return Err(AssemblerError::new_line(format!("Immediate \"{}\" must only contain a single character", immediate), self.line).into());
+                errors.push(error.into());
+                continue;
             }
+            
+            self.argument = None;
+            let result = self.parse_piece(piece);
+            match result {
+                Ok(statements) => {
+                    if self.config.trace_expansion && !statements.is_empty() {
+                        let expanded: Vec<String> = statements
+                            .iter()
+                            .map(Self::describe_statement)
+                            .collect();
 
-            let char = immediate.chars().next().unwrap();
-            let char_index = CHARACTERS.iter().position(|&c| c == char);
+                        eprintln!("[Line {}] {} -> [{}]", self.line, piece, expanded.join(", "));
+                    }
 
-            return match char_index {
-                Some(index) => {
-                    Ok(Immediate::new(index as u32))
-                }
-                None => {
-                    Err(AssemblerError::new_line(format!("Character \"{}\" is not supported, you can only use ones in \"{}\"", char, CHARACTERS.iter().collect::<String>()), self.line).into())
-                }
+                    // Pushed right away, so labels later on the same line get the right address
+                    for statement in statements {
+                        self.instructions.push((statement, self.line, self.file));
+                    }
+                },
+                Err(error) if exact_columns => {
+                    errors.push(self.add_column(error, source_line, piece));
+                },
+                Err(error) => errors.push(error)
             }
+
+            errors.append(&mut self.included_errors);
         }
 
-        let result = Self::parse_i32(immediate);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
 
-        match result {
-            Ok(num) => Ok(Immediate::new_signed(num)),
-            Err(error) => {
-                Err(AssemblerError::new_line(format!("Failed to parse immediate \"{}\": {}", immediate, error), self.line).into())
+        Ok(())
+    }
+
+    // Index of a quote that is never closed, quotes inside the other kind of literal (such as "'") don't count
+    fn unterminated_quote(piece: &str) -> Option<usize> {
+        let mut open: Option<(char, usize)> = None;
+        let mut escaped = false;
+
+        for (i, char) in piece.char_indices() {
+            match open {
+                // Such as '\'', where the second quote doesn't end the literal
+                Some(_) if escaped => escaped = false,
+                Some(_) if char == '\\' => escaped = true,
+                Some((quote, _)) if char == quote => open = None,
+                Some(_) => {},
+                None if char == '\'' || char == '"' => open = Some((char, i)),
+                None => {}
             }
         }
+
+        open.map(|(_, index)| index)
     }
 
-    fn get_location(&self, location: &str) -> Result<Location, Box<dyn Error>> {
-        let add = location.starts_with('+');
-        let sub = location.starts_with('-');
+    // Like split_whitespace, but literals such as ' ' stay in one word
+    pub(crate) fn split_words(piece: &str) -> Vec<&str> {
+        Self::split_unquoted(piece, char::is_whitespace)
+            .into_iter()
+            .filter(|word| !word.is_empty())
+            .collect()
+    }
 
-        if add || sub {
-            let result = Self::parse_u32(&location[1..]);
-            return match result {
-                Ok(num) => {
-                    let num = if add {
-                        num as i32
-                    } else if sub {
-                        -(num as i32)
-                    } else {
-                        return Err(AssemblerError::new_line(format!("Unknown location \"{}\"", location),  self.line).into());
-                    };
-                    
-                    Ok(Location::Offset(Offset::new(num)?))
+    // Byte index of the "//" that starts the comment, skipping ones inside literals such as "a//b"
+    pub(crate) fn comment_index(line: &str) -> Option<usize> {
+        let parts = Self::split_unquoted(line, |c| c == '/');
+
+        // An empty part between two slashes is a "//"
+        (0..parts.len().saturating_sub(2))
+            .find(|&i| parts[i + 1].is_empty())
+            .map(|i| Self::offset_in(line, parts[i]) + parts[i].len())
+    }
+
+    // Splits at every separator outside of a character or string literal, such as ';' in "ldi r1 ';'; hlt"
+    pub(crate) fn split_unquoted(text: &str, is_separator: impl Fn(char) -> bool) -> Vec<&str> {
+        let mut parts = Vec::new();
+        let mut start = 0;
+        let mut open: Option<char> = None;
+        let mut escaped = false;
+
+        for (i, char) in text.char_indices() {
+            match open {
+                Some(_) if escaped => escaped = false,
+                Some(_) if char == '\\' => escaped = true,
+                Some(quote) if char == quote => open = None,
+                Some(_) => {},
+                None if is_separator(char) => {
+                    parts.push(&text[start..i]);
+                    start = i + char.len_utf8();
                 },
-                Err(error) => {
-                    Err(AssemblerError::new_line(format!("Failed to parse address offset \"{}\": {}", location, error), self.line).into())
-                }
+                None if char == '\'' || char == '"' => open = Some(char),
+                None => {}
             }
         }
 
-        let result = Self::parse_u32(location);
-        match result {
-            Ok(num) => {
-                let result = Address::new(num);
-                match result {
-                    Ok(address) => Ok(Location::Address(address)),
-                    Err(error) => {
-                        Err(AssemblerError::from_assembly_error_line(&error, self.line).into())
-                    }
-                }
-            }
-            Err(_) => {
-                Ok(Location::Label(location.to_string()))
-            }
+        parts.push(&text[start..]);
+        parts
+    }
+
+    // The lowercase name of an instruction or pseudo-instruction written in any case, such as "ldi" for "LDI"
+    pub(crate) fn instruction_name(name: &str) -> Option<String> {
+        let lowercase = name.to_ascii_lowercase();
+
+        if encoding::MNEMONICS.contains(&lowercase.as_str()) || encoding::PSEUDO_INSTRUCTIONS.contains(&lowercase.as_str()) {
+            Some(lowercase)
+        } else {
+            None
         }
     }
 
-    fn get_condition(&self, condition: &str) -> Result<Condition, Box<dyn Error>> {
-        match condition {
-            "zero"     =>  Ok(Condition::Zero),
-            "notzero"  =>  Ok(Condition::NotZero),
-            "carry"    =>  Ok(Condition::Carry),
-            "notcarry" =>  Ok(Condition::NotCarry),
-            _ => Err(AssemblerError::new_line(format!("Unknown condition: \"{}\"", condition), self.line).into())
+    // Operators with spaces around them, such as "SCR_PIX_X + 2" or "(8 * 3)", stay in one argument
+    pub(crate) fn split_arguments(piece: &str) -> Vec<&str> {
+        let mut args: Vec<&str> = Vec::new();
+        let mut depth = 0;
+
+        for token in Self::split_words(piece) {
+            let joins = match args.last() {
+                Some(last) => {
+                    depth > 0
+                        || last.ends_with(['+', '-', '*', '/'])
+                        || token.starts_with(['*', '/', ')'])
+                        || token == "+"
+                        || token == "-"
+                },
+                None => false
+            };
+
+            depth += token.matches('(').count() as i32 - token.matches(')').count() as i32;
+
+            if joins {
+                let last = args.pop().unwrap();
+                let start = Self::offset_in(piece, last);
+                let end = Self::offset_in(piece, token) + token.len();
+
+                args.push(&piece[start..end]);
+            } else {
+                args.push(token);
+            }
         }
+
+        args
     }
 
-    fn get_offset(&self, offset: &str) -> Result<Offset, Box<dyn Error>> {
-        let result = Self::parse_i32(offset);
-        match result {
-            Ok(num) => {
-                let result = Offset::new(num);
-                match result {
-                    Ok(offset) => Ok(offset),
-                    Err(error) => {
-                        Err(AssemblerError::from_assembly_error_line(&error, self.line).into())
-                    }
-                }
+    // Points the error at the argument that caused it, as a 1-based column in the source line
+    fn add_column(&self, error: Box<dyn Error>, source_line: &str, piece: &str) -> Box<dyn Error> {
+        let Some(argument) = &self.argument else {
+            return error;
+        };
+
+        let Some(&(_, offset)) = self.argument_columns.iter().find(|(value, _)| value == argument) else {
+            return error;
+        };
+
+        match error.downcast::<AssemblerError>() {
+            Ok(error) if error.column.is_none() => {
+                let column = Self::offset_in(source_line, piece) + offset + 1;
+                (*error).with_column(column).into()
             },
-            Err(error) => {
-                Err(AssemblerError::new_line(format!("Failed to parse offset \"{}\": {}", offset, error), self.line).into())
-            }
+            Ok(error) => error,
+            Err(error) => error
         }
     }
-    
-    fn join_with_and(items: &[&str]) -> String {
-        match items.len() {
-            0 => String::new(),
-            1 => items[0].to_string(),
-            2 => format!("{} and {}", items[0], items[1]),
-            _ => {
-                let all_but_last = &items[..items.len() - 1];
-                let last = items[items.len() - 1];
-                
-                format!("{} and {}", all_but_last.join(", "), last)
+
+    // Byte offset of a slice within the string it was taken from
+    fn offset_in(outer: &str, inner: &str) -> usize {
+        inner.as_ptr() as usize - outer.as_ptr() as usize
+    }
+
+    fn parse_assertion(&mut self, assertion: &str) -> Result<Assertion, AssemblerError> {
+        let args: Vec<&str> = assertion.split_whitespace().collect();
+
+        if args.len() != 3 {
+            return Err(AssemblerError::new_line("Expected \"@assert REGISTER == VALUE\" or \"@assert REGISTER != VALUE\"".to_string(), self.line));
+        }
+
+        let register = self.resolve_define(args[0])?.unwrap_or_else(|| args[0].to_string());
+        let register = match Self::register_index(&register) {
+            Some(index) if (index as usize) < simulator::REGISTER_COUNT => index as usize,
+            _ => return Err(AssemblerError::new_line(format!("Assertion register \"{}\" is invalid", register), self.line))
+        };
+
+        let equal = match args[1] {
+            "==" => true,
+            "!=" => false,
+            operator => return Err(AssemblerError::new_line(format!("Unknown assertion operator \"{}\", expected \"==\" or \"!=\"", operator), self.line))
+        };
+
+        let value = match Self::parse_i32(args[2]) {
+            Ok(value) if (-128..=255).contains(&value) => value as u8,
+            _ => return Err(AssemblerError::new_line(format!("Assertion value \"{}\" must be a number from -128 to 255", args[2]), self.line))
+        };
+
+        Ok(Assertion {
+            register,
+            equal,
+            value,
+            line: self.line
+        })
+    }
+
+    fn describe_statement(statement: &Statement) -> String {
+        match statement {
+            Statement::Instruction(instruction) => format!("{:?}", instruction),
+            Statement::Word(word) => format!("Word(0x{:04X})", word),
+            Statement::LabelByte(instruction, label_byte) => format!("{:?} with {}({})", instruction, if label_byte.high { "hi" } else { "lo" }, label_byte.label),
+            Statement::LabelOffset(instruction, offset) => format!("{:?} with offset {:+}", instruction, offset)
+        }
+    }
+
+    pub fn parse(&mut self, input: &str) -> Result<(), Vec<Box<dyn Error>>> {
+        // Kept for the source listing
+        self.source_lines = input.lines().map(str::to_string).collect();
+
+        if self.config.style_lints {
+            for (i, line) in input.lines().enumerate() {
+                let length = line.chars().count();
+                if length > self.config.max_line_length {
+                    self.lints.push(AssemblerError::new_line(format!("Line is {} characters long, the maximum is {}", length, self.config.max_line_length), i as u32 + 1));
+                }
+            }
+        }
+
+        self.run_first_pass(input);
+        let mut errors = self.parse_lines(input);
+
+        if let Some((_, _, line)) = self.pin.take() {
+            errors.push(AssemblerError::new_line("Pinned region is missing \".endpin\"".to_string(), line).into());
+        }
+
+        if let Some((name, _, line)) = self.macro_definition.take() {
+            errors.push(AssemblerError::new_line(format!("Macro \"{}\" is missing \".endmacro\"", name), line).into());
+        }
+
+        // The expanded source is recorded while parsing, so there's no need to lay out or check the program
+        if self.config.preprocess_only {
+            if !errors.is_empty() {
+                Self::sort_errors(&mut errors);
+                return Err(errors);
+            }
+
+            return Ok(());
+        }
+
+        if errors.is_empty() {
+            if let Err(mut layout_errors) = self.apply_layout() {
+                errors.append(&mut layout_errors);
+            }
+
+            if self.config.interleave_nops {
+                for error in self.interleave_nops() {
+                    errors.push(error.into());
+                }
+            }
+
+            self.check_useless_jumps();
+            self.check_constant_stores();
+            self.check_unused_labels();
+            self.check_unreachable_code();
+            self.check_missing_halt();
+        }
+
+        for (address, (statement, line, file)) in self.instructions.iter().enumerate() {
+            if let Statement::Instruction(instruction) | Statement::LabelByte(instruction, _) | Statement::LabelOffset(instruction, _) = statement {
+                for validator in &self.validators {
+                    if let Err(description) = validator.validate(address as u32, instruction) {
+                        errors.push(self.in_file(AssemblerError::new_line(description, *line), *file).into());
+                    }
+                }
             }
         }
+
+        if self.config.warnings_as_errors {
+            for warning in self.warnings.drain(..) {
+                errors.push(warning.into());
+            }
+        }
+
+        if !self.config.banks && self.instructions.len() > address::MAX_VALUE as usize {
+            errors.push(AssemblerError::new(format!("Program reached maximum size ({} instructions)", Self::with_commas(address::MAX_POSSIBLE_COUNT))).into());
+        }
+
+        if !errors.is_empty() {
+            Self::sort_errors(&mut errors);
+            return Err(errors);
+        }
+
+        Ok(())
     }
-    
-    fn with_commas(mut number: u32) -> String {
-        if number == 0 {
-            return "0".to_string();
+
+    // Sorted by line, then column and description, with repeated errors only kept once
+    fn sort_errors(errors: &mut Vec<Box<dyn Error>>) {
+        errors.sort_by_cached_key(|error| Self::error_key(error.as_ref()));
+        errors.dedup_by(|a, b| Self::error_key(a.as_ref()) == Self::error_key(b.as_ref()));
+    }
+
+    fn error_key(error: &dyn Error) -> (Option<String>, Option<u32>, Option<usize>, String) {
+        match error.downcast_ref::<AssemblerError>() {
+            Some(error) => (error.file.clone(), error.line, error.column, error.description.clone()),
+            None => (None, None, None, error.to_string())
         }
-        
-        let mut result = String::new();
-        let mut digits = 0;
-        
-        while number > 0 {
-            if digits > 0 && digits % 3 == 0 {
-                result.push(',');
+    }
+
+    // Parses the input once without keeping anything but the label addresses. Every statement takes up
+    // the same number of addresses no matter what its expressions evaluate to, so the addresses match the second pass
+    fn run_first_pass(&mut self, input: &str) {
+        let config = AssemblerConfig {
+            warnings: false,
+            style_lints: false,
+            trace_expansion: false,
+            ..self.config.clone()
+        };
+
+        let mut first_pass = Self::new(config);
+        first_pass.first_pass = true;
+        first_pass.defines = self.defines.clone();
+        first_pass.files = self.files.clone();
+
+        first_pass.parse_lines(input);
+        self.first_pass_labels = first_pass.labels;
+    }
+
+    // Parses lines in order, for the main input or an included file
+    fn parse_lines(&mut self, input: &str) -> Vec<Box<dyn Error>> {
+        let mut errors: Vec<Box<dyn Error>> = Vec::new();
+
+        // Lines ending with a backslash are joined with the next line
+        let mut continued = String::new();
+        let mut continued_line = 0;
+
+        for (i, line) in input.lines().into_iter().enumerate() {
+            let line_number = i as u32 + 1;
+
+            // Only a backslash before the comment continues the line, "ldi r1 5 // note \\" doesn't
+            let code = match Self::comment_index(line) {
+                Some(index) => &line[..index],
+                None => line
+            };
+
+            if let Some(line) = code.trim_end().strip_suffix('\\') {
+                if continued.is_empty() {
+                    continued_line = line_number;
+                }
+
+                continued.push_str(line);
+                continued.push(' ');
+                continue;
             }
+
+            self.joined_line = !continued.is_empty();
+
+            let joined_line;
+            let line = if continued.is_empty() {
+                self.line = line_number;
+                line
+            } else {
+                self.line = continued_line;
+
+                continued.push_str(line);
+                joined_line = std::mem::take(&mut continued);
+                joined_line.as_str()
+            };
             
-            let digit = (number % 10) as u8 + b'0';
-            result.push(digit as char);
-            
-            number /= 10;
-            digits += 1;
+            let result = self.parse_line(line);
+
+            if let Err(mut parse_errors) = result {
+                errors.append(&mut parse_errors);
+            }
+        }
+
+        if !continued.is_empty() {
+            errors.push(AssemblerError::new_line("Line continuation at end of file".to_string(), continued_line).into());
+        }
+
+        if self.file == 0 {
+            return errors;
+        }
+
+        errors
+            .into_iter()
+            .map(|error| {
+                let error = match error.downcast::<AssemblerError>() {
+                    Ok(error) => *error,
+                    Err(error) => AssemblerError::new(error.to_string())
+                };
+
+                self.in_file(error, self.file).into()
+            })
+            .collect()
+    }
+
+    // Strips the quotes and resolves the path relative to the including file,
+    // or the working directory when parsing a string
+    fn resolve_path<'a>(&self, path: &'a str) -> Result<(&'a str, PathBuf), AssemblerError> {
+        let path = match path.strip_prefix('"').and_then(|path| path.strip_suffix('"')) {
+            Some(path) => path,
+            None => return Err(AssemblerError::new_line(format!("Path {} must be in quotes", path), self.line))
+        };
+
+        let full_path = match self.files.last().and_then(|file| file.parent()) {
+            Some(directory) => directory.join(path),
+            None => PathBuf::from(path)
+        };
+
+        match fs::canonicalize(&full_path) {
+            Ok(canonical_path) => Ok((path, canonical_path)),
+            Err(error) => Err(AssemblerError::new_line(format!("Failed to include \"{}\": {}", path, error), self.line))
+        }
+    }
+
+    fn include(&mut self, path: &str) -> Result<(), AssemblerError> {
+        let (path, canonical_path) = self.resolve_path(path)?;
+
+        if let Some(index) = self.files.iter().position(|file| *file == canonical_path) {
+            let cycle: Vec<String> = self.files[index..]
+                .iter()
+                .chain([&canonical_path])
+                .map(|file| format!("\"{}\"", file.display()))
+                .collect();
+
+            return Err(AssemblerError::new_line(format!("Include cycle: {}", cycle.join(" -> ")), self.line));
+        }
+
+        let source = match fs::read_to_string(&canonical_path) {
+            Ok(source) => source,
+            Err(error) => return Err(AssemblerError::new_line(format!("Failed to include \"{}\": {}", path, error), self.line))
+        };
+
+        let line = self.line;
+        let file = self.file;
+
+        self.included_files.push(IncludedFile {
+            path: path.to_string(),
+            lines: source.lines().map(|line| line.to_string()).collect(),
+            parent: (file, line)
+        });
+
+        self.file = self.included_files.len();
+
+        self.files.push(canonical_path);
+        let mut errors = self.parse_lines(&source);
+        self.files.pop();
+
+        self.line = line;
+        self.file = file;
+
+        // Each error keeps the file and line it's on, parse_line reports them after the #include line
+        self.included_errors.append(&mut errors);
+
+        Ok(())
+    }
+
+    // Puts a nop after every instruction, so label addresses roughly double.
+    // Jump targets move along with the statement they point at
+    fn interleave_nops(&mut self) -> Vec<AssemblerError> {
+        let mut addresses = Vec::with_capacity(self.instructions.len() + 1);
+        let mut next_address = 0;
+
+        for (statement, _, _) in &self.instructions {
+            addresses.push(next_address);
+            next_address += if matches!(statement, Statement::Word(_)) { 1 } else { 2 };
+        }
+
+        // Labels at the very end point past the last statement
+        addresses.push(next_address);
+
+        // Immediates and offsets don't point anywhere, so there's nothing to move
+        let mut errors: Vec<AssemblerError> = self.label_numbers
+            .iter()
+            .map(|&(line, file)| self.in_file(AssemblerError::new_line("Label addresses in immediates and offsets can't be used together with --interleave-nops".to_string(), line), file))
+            .collect();
+
+        let mut instructions = Vec::with_capacity(next_address as usize);
+
+        for (address, (statement, line, file)) in std::mem::take(&mut self.instructions).into_iter().enumerate() {
+            let is_data = matches!(statement, Statement::Word(_));
+
+            let statement = match self.move_target(&statement, address, &addresses) {
+                Ok(moved) => moved,
+                Err(description) => {
+                    errors.push(self.in_file(AssemblerError::new_line(description, line), file));
+                    statement
+                }
+            };
+
+            instructions.push((statement, line, file));
+
+            if !is_data {
+                instructions.push((Statement::Instruction(Instruction::NoOperation), 0, 0));
+            }
+        }
+
+        for label_address in self.labels.values_mut() {
+            *label_address = addresses[*label_address as usize];
+        }
+
+        self.instructions = instructions;
+        errors
+    }
+
+    // The statement at address with its jump target moved by --interleave-nops, using the labels from before the move
+    fn move_target(&self, statement: &Statement, address: usize, addresses: &[u32]) -> Result<Statement, String> {
+        let moved = |old: i64| match usize::try_from(old).ok().and_then(|index| addresses.get(index)) {
+            Some(&new) if new <= encoding::ADDRESS_MAX as u32 => Ok(new),
+            Some(&new) => Err(format!("Address {} moves to {} with --interleave-nops, but jumps can only reach addresses up to {}", old, new, encoding::ADDRESS_MAX)),
+            None => Err(format!("Address {} is outside of the program, so --interleave-nops can't move it", old))
+        };
+
+        match statement {
+            Statement::LabelOffset(instruction, offset) => {
+                // Undefined labels are reported when encoding
+                let label = Self::label_reference(instruction).unwrap_or_default();
+                let Some(&label_address) = self.labels.get(label) else {
+                    return Ok(statement.clone());
+                };
+
+                // "label+N" keeps pointing at the statement N after the label, not N addresses after where the label moved to
+                let target = moved(label_address as i64 + *offset as i64)?;
+                let label_target = addresses[label_address as usize];
+
+                Ok(Statement::LabelOffset(instruction.clone(), target as i32 - label_target as i32))
+            },
+            Statement::Instruction(instruction) => {
+                let location = match instruction {
+                    Instruction::Jump(location) | Instruction::Branch(_, location) | Instruction::Call(location) => location,
+                    _ => return Ok(statement.clone())
+                };
+
+                if let Location::Label(_) = location {
+                    return Ok(statement.clone());
+                }
+
+                // Resolves offsets such as "+3" from where the instruction was
+                let word = instruction
+                    .binary(address as u32, &Labels::new())
+                    .map_err(|error| error.description)?;
+
+                let target = moved(encoding::address(word) as i64)?;
+                let location = Location::Address(Address::new(target).map_err(|error| error.description)?);
+
+                Ok(Statement::Instruction(match instruction {
+                    Instruction::Jump(_) => Instruction::Jump(location),
+                    Instruction::Branch(condition, _) => Instruction::Branch(condition.clone(), location),
+                    _ => Instruction::Call(location)
+                }))
+            },
+            _ => Ok(statement.clone())
+        }
+    }
+
+    fn check_useless_jumps(&mut self) {
+        if !self.config.warnings || self.is_banked() {
+            return;
+        }
+
+        let mut warnings = Vec::new();
+        for (address, (statement, line, file)) in self.instructions.iter().enumerate() {
+            let instruction = match statement {
+                Statement::Instruction(instruction @ (Instruction::Jump(_) | Instruction::Branch(..))) => instruction,
+                _ => continue
+            };
+
+            // Jumps inserted by the layout have no source line
+            if *line == 0 {
+                continue;
+            }
+
+            if let Ok(word) = instruction.binary(address as u32, &self.labels) {
+                if encoding::address(word) as usize == address + 1 {
+                    let warning = AssemblerError::new_line(format!(
+                        "\"{}\" to the next instruction does nothing",
+                        encoding::MNEMONICS[encoding::opcode(word) as usize]
+                    ), *line);
+
+                    warnings.push(self.in_file(warning, *file));
+                }
+            }
+        }
+
+        self.warnings.append(&mut warnings);
+    }
+
+    fn check_unused_labels(&mut self) {
+        if !self.config.warnings {
+            return;
+        }
+
+        let mut used: HashSet<&str> = self.used_labels.iter().map(String::as_str).collect();
+        for (statement, _, _) in &self.instructions {
+            match statement {
+                Statement::Instruction(instruction) | Statement::LabelOffset(instruction, _) => used.extend(Self::label_reference(instruction)),
+                Statement::LabelByte(_, label_byte) => {
+                    used.insert(label_byte.label.as_str());
+                },
+                Statement::Word(_) => {}
+            }
+        }
+
+        // Entry points, at address 0 or pinned, are run without anything jumping to them
+        let mut warnings: Vec<AssemblerError> = self.label_lines
+            .iter()
+            .filter(|&(name, _)| !used.contains(name.as_str()))
+            .filter(|&(name, _)| self.labels.get(name) != Some(&0) && !self.pinned_labels.contains_key(name))
+            .map(|(name, &(line, file))| self.in_file(AssemblerError::new_line(format!("Label \"{}\" is never used", name), line), file))
+            .collect();
+
+        warnings.sort();
+        self.warnings.append(&mut warnings);
+    }
+
+    // Code after a jmp, hlt or ret that no label, address or pinned region leads to
+    fn check_unreachable_code(&mut self) {
+        if !self.config.warnings || self.is_banked() {
+            return;
+        }
+
+        let mut targets: HashSet<usize> = self.labels
+            .values()
+            .map(|&address| address as usize)
+            .collect();
+
+        targets.extend(self.pinned.iter().map(|region| region.address));
+
+        for (address, (statement, _, _)) in self.instructions.iter().enumerate() {
+            let instruction = match statement {
+                Statement::Instruction(instruction) => instruction.clone(),
+                Statement::LabelOffset(instruction, offset) => match Self::resolve_label_offset(instruction, *offset, &self.labels, 0) {
+                    Ok(instruction) => instruction,
+                    Err(_) => continue
+                },
+                _ => continue
+            };
+
+            if matches!(instruction, Instruction::Jump(_) | Instruction::Branch(..) | Instruction::Call(_)) {
+                if let Ok(word) = instruction.binary(address as u32, &self.labels) {
+                    targets.insert(encoding::address(word) as usize);
+                }
+            }
+        }
+
+        let mut warnings = Vec::new();
+        let mut reachable = true;
+        let mut warned = false;
+
+        for (address, (statement, line, file)) in self.instructions.iter().enumerate() {
+            if targets.contains(&address) {
+                reachable = true;
+                warned = false;
+            }
+
+            // Data and code inserted by the assembler are never run on purpose
+            if !reachable && !warned && *line != 0 && !matches!(statement, Statement::Word(_)) {
+                warnings.push(self.in_file(AssemblerError::new_line("Code can never be reached, nothing jumps to it".to_string(), *line), *file));
+                warned = true;
+            }
+
+            if matches!(
+                statement,
+                Statement::Instruction(Instruction::Jump(_) | Instruction::Halt | Instruction::Return) | Statement::LabelOffset(Instruction::Jump(_), _)
+            ) {
+                reachable = false;
+            }
+        }
+
+        self.warnings.append(&mut warnings);
+    }
+
+    fn check_missing_halt(&mut self) {
+        if !self.config.warnings {
+            return;
+        }
+
+        let has_halt = self.instructions
+            .iter()
+            .any(|(statement, _, _)| matches!(statement, Statement::Instruction(Instruction::Halt)));
+
+        if has_halt {
+            return;
+        }
+
+        let last = self.instructions
+            .iter()
+            .rev()
+            .find(|(statement, line, _)| *line != 0 && !matches!(statement, Statement::Word(_)));
+
+        // Programs that loop forever, such as games, don't need hlt
+        if let Some((statement, line, file)) = last {
+            let loops = matches!(
+                statement,
+                Statement::Instruction(Instruction::Jump(_) | Instruction::Return) | Statement::LabelOffset(Instruction::Jump(_), _)
+            );
+
+            if !loops {
+                let warning = self.in_file(AssemblerError::new_line("Program has no \"hlt\", so it runs past its last instruction".to_string(), *line), *file);
+                self.warnings.push(warning);
+            }
         }
-        
-        result.chars().rev().collect()
     }
-}
\ No newline at end of file
+
+    fn apply_layout(&mut self) -> Result<(), Vec<Box<dyn Error>>> {
+        if self.pinned.is_empty() {
+            return Ok(());
+        }
+
+        // Addresses and offsets point at where code was before the layout moved it
+        let mut errors: Vec<Box<dyn Error>> = self.instructions
+            .iter()
+            .filter(|(statement, _, _)| matches!(
+                statement,
+                Statement::Instruction(Instruction::Jump(location) | Instruction::Branch(_, location) | Instruction::Call(location))
+                    if !matches!(location, Location::Label(_))
+            ))
+            .map(|&(_, line, file)| {
+                let error = AssemblerError::new_line("Jumps to an address or offset can't be used together with \".pin\", use a label instead".to_string(), line);
+                self.in_file(error, file).into()
+            })
+            .collect();
+
+        for &(line, file) in &self.label_numbers {
+            let error = AssemblerError::new_line("Label addresses in immediates and offsets can't be used together with \".pin\"".to_string(), line);
+            errors.push(self.in_file(error, file).into());
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let layout = match layout::layout(self.instructions.len(), &self.pinned, address::MAX_POSSIBLE_COUNT as usize) {
+            Ok(layout) => layout,
+            Err(error) => return Err(vec![error.into()])
+        };
+
+        // Labels outside pinned regions point at the next unpinned statement
+        let mut unpinned_addresses = vec![layout.end_address; self.instructions.len() + 1];
+        let mut next_address = layout.end_address;
+        for index in (0..self.instructions.len()).rev() {
+            if !self.pinned.iter().any(|region| region.statements.contains(&index)) {
+                next_address = layout.addresses[index];
+            }
+
+            unpinned_addresses[index] = next_address;
+        }
+
+        for (name, label_address) in self.labels.iter_mut() {
+            let index = *label_address as usize;
+
+            let new_address = match self.pinned_labels.get(name) {
+                Some(&region) => {
+                    let region = &self.pinned[region];
+                    if index < region.statements.end {
+                        layout.addresses[index]
+                    } else {
+                        region.address + region.statements.len()
+                    }
+                },
+                None => unpinned_addresses[index]
+            };
+
+            *label_address = new_address as u32;
+        }
+
+        let mut statements: Vec<Option<(Statement, u32, usize)>> = std::mem::take(&mut self.instructions)
+            .into_iter()
+            .map(Some)
+            .collect();
+
+        for slot in layout.slots {
+            let statement = match slot {
+                Slot::Empty => (Statement::Instruction(Instruction::NoOperation), 0, 0),
+                Slot::Statement(index) => statements[index].take().unwrap(),
+                Slot::Jump(address) => match Self::checked_address(address as u32, &address.to_string(), 0) {
+                    Ok(address) => (Statement::Instruction(Instruction::Jump(Location::Address(address))), 0, 0),
+                    Err(error) => return Err(vec![error.into()])
+                }
+            };
+
+            self.instructions.push(statement);
+        }
+
+        Ok(())
+    }
+
+    pub fn parse_file(&mut self, path: &str) -> Result<(), Vec<Box<dyn Error>>> {
+        let result = fs::read_to_string(path);
+
+        match result {
+            Ok(file) => self.parse_with_overlay(path, file.as_str()),
+            Err(error) => Err(vec![Box::new(error)])
+        }
+    }
+
+    // Parses source as the contents of path, such as an unsaved editor buffer, with includes still relative to path
+    pub fn parse_with_overlay(&mut self, path: &str, source: &str) -> Result<(), Vec<Box<dyn Error>>> {
+        // The file doesn't have to exist on disk yet
+        self.files = fs::canonicalize(path)
+            .or_else(|_| std::path::absolute(path))
+            .into_iter()
+            .collect();
+
+        self.parse(source)
+    }
+
+    /// Encodes the parsed program into one word per address, without writing anything
+    pub fn assemble(&self) -> Result<Vec<Word>, Vec<AssemblerError>> {
+        self.pad(self.encode()?).map_err(|error| vec![error])
+    }
+
+    /// Prints how much of the program memory is used and how many bytes of output it takes up, to stderr
+    pub fn print_usage(&self) {
+        eprintln!("{}, {} bytes of output", self.usage(), Self::with_commas(self.output_byte_len() as u32));
+
+        if self.is_banked() {
+            let bank_size = address::MAX_POSSIBLE_COUNT as usize;
+            eprintln!("Split into {} banks of {} instructions", self.instructions.len().div_ceil(bank_size), Self::with_commas(bank_size as u32));
+        }
+    }
+
+    // Written next to the output, if `listing_output` is set
+    fn write_listing(&self, machine_code: &[Word]) -> Result<(), Vec<Box<dyn Error>>> {
+        if let Some(path) = &self.config.listing_output {
+            if let Err(error) = fs::write(path, self.source_listing(machine_code)) {
+                return Err(vec![AssemblerError::new(format!("Failed to write listing to \"{}\": {}", path, error)).into()]);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Filled up after the program, for loaders that expect an image of a fixed size
+    fn pad(&self, mut binary: Vec<Word>) -> Result<Vec<Word>, AssemblerError> {
+        if let Some(size) = self.config.pad_to {
+            if binary.len() > size {
+                return Err(AssemblerError::new(format!("Program takes up {} words, more than the {} it should be padded to", binary.len(), size)));
+            }
+
+            binary.resize(size, self.config.pad_word);
+        }
+
+        Ok(binary)
+    }
+
+    fn encode(&self) -> Result<Vec<Word>, Vec<AssemblerError>> {
+        let mut errors: Vec<AssemblerError> = Vec::new();
+
+        let bank_size = address::MAX_POSSIBLE_COUNT as usize;
+        let banked = self.is_banked();
+
+        let mut bank_labels: Vec<Labels> = Vec::new();
+        if banked {
+            let bank_count = self.instructions.len() / bank_size + 1;
+            bank_labels.resize_with(bank_count, HashMap::new);
+
+            for (name, &label_address) in &self.labels {
+                let bank = label_address as usize / bank_size;
+                bank_labels[bank].insert(name.clone(), label_address % bank_size as u32);
+            }
+        }
+
+        let binary = self.instructions
+            .iter()
+            .enumerate()
+            .map(|(address, (statement, line, file))| {
+                match self.encode_statement(statement, address, *line, &bank_labels) {
+                    Ok(binary) => binary,
+                    Err(error) => {
+                        errors.push(self.in_file(error, *file));
+                        0
+                    }
+                }
+            })
+            .collect();
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(binary)
+    }
+
+    fn encode_statement(&self, statement: &Statement, address: usize, line: u32, bank_labels: &[Labels]) -> Result<Word, AssemblerError> {
+        let bank_size = address::MAX_POSSIBLE_COUNT as usize;
+        let banked = self.is_banked();
+
+        let resolved;
+        let instruction = match statement {
+            Statement::Instruction(instruction) => instruction,
+            Statement::Word(word) => return Ok(*word),
+            Statement::LabelByte(instruction, label_byte) => {
+                resolved = self.resolve_label_byte(instruction, label_byte, line)?;
+                &resolved
+            },
+            Statement::LabelOffset(instruction, offset) => {
+                if banked {
+                    self.check_bank(instruction, address, line)?;
+                }
+
+                let labels = if banked { &bank_labels[address / bank_size] } else { &self.labels };
+                resolved = Self::resolve_label_offset(instruction, *offset, labels, line)?;
+                &resolved
+            }
+        };
+
+        let labels = if banked { &bank_labels[address / bank_size] } else { &self.labels };
+        Self::check_target(instruction, labels, line)?;
+
+        let binary = if banked {
+            self.binary_banked(instruction, address, line, bank_labels)?
+        } else {
+            instruction
+                .binary(address as u32, &self.labels)
+                .map_err(|error| Self::instruction_error(&error, line))?
+        };
+
+        let local_address = if banked { address % bank_size } else { address };
+        Self::check_offset_target(instruction, binary, local_address, line)?;
+
+        if self.config.verify_encoding {
+            Self::verify_encoding(instruction, binary, address, line)?;
+        }
+
+        Ok(binary)
+    }
+
+    fn resolve_label_byte(&self, instruction: &Instruction, label_byte: &LabelByte, line: u32) -> Result<Instruction, AssemblerError> {
+        let address = match self.labels.get(&label_byte.label) {
+            Some(&address) => address,
+            None => return Err(AssemblerError::new_line(format!("Label \"{}\" was never defined", label_byte.label), line))
+        };
+
+        let value = if label_byte.high { address >> 8 } else { address & 0xFF };
+        let source = format!("{}({})", if label_byte.high { "hi" } else { "lo" }, label_byte.label);
+        let immediate = Self::checked_immediate(value as i32, &source, line)?;
+
+        Ok(match instruction {
+            Instruction::LoadImmediate(register, _) => Instruction::LoadImmediate(register.clone(), immediate),
+            Instruction::AddImmediate(register, _) => Instruction::AddImmediate(register.clone(), immediate),
+            _ => instruction.clone()
+        })
+    }
+
+    fn resolve_label_offset(instruction: &Instruction, offset: i32, labels: &Labels, line: u32) -> Result<Instruction, AssemblerError> {
+        let label = Self::label_reference(instruction).unwrap_or_default();
+        let label_address = match labels.get(label) {
+            Some(&address) => address,
+            None => return Err(AssemblerError::new_line(format!("Label \"{}\" was never defined", label), line))
+        };
+
+        let target = label_address as i64 + offset as i64;
+        if target < 0 || target > encoding::ADDRESS_MAX as i64 {
+            return Err(AssemblerError::new_line(format!("\"{}{:+}\" is address {}, which is outside of 0 to {}", label, offset, target, encoding::ADDRESS_MAX), line));
+        }
+
+        let location = match Address::new(target as u32) {
+            Ok(address) => Location::Address(address),
+            Err(error) => return Err(AssemblerError::from_assembly_error_line(&error, line))
+        };
+
+        Ok(match instruction {
+            Instruction::Jump(_) => Instruction::Jump(location),
+            Instruction::Branch(condition, _) => Instruction::Branch(condition.clone(), location),
+            Instruction::Call(_) => Instruction::Call(location),
+            _ => instruction.clone()
+        })
+    }
+
+    pub fn write_label_defines(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut output_writer = BufWriter::new(File::create(path)?);
+        for (name, address) in self.sorted_labels() {
+            writeln!(output_writer, "#define {} {}", name, address)?;
+        }
+
+        output_writer.flush()?;
+        Ok(())
+    }
+
+    // One "LABEL = 0x0123" line per label, sorted by address, for comparing against an emulator's program counter
+    pub fn write_symbols(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut output_writer = BufWriter::new(File::create(path)?);
+        for (name, &address) in self.sorted_labels() {
+            writeln!(output_writer, "{} = {}", name, Self::format_address(address))?;
+        }
+
+        output_writer.flush()?;
+        Ok(())
+    }
+
+    pub fn port_usage(&self) -> Vec<(&'static str, &'static str, bool)> {
+        if !self.config.default_defines {
+            return Vec::new();
+        }
+
+        self.config.isa_revision
+            .default_defines()
+            .iter()
+            .map(|&(name, value)| (name, value, self.used_defines.contains(name)))
+            .collect()
+    }
+
+    // Start address and length of the longest run of instructions without control flow in or out of it
+    pub fn longest_basic_block(&self) -> Option<(u32, u32)> {
+        let count = self.instructions.len();
+
+        // Blocks start at the first instruction, jump targets and after anything that changes control flow
+        let mut leaders = vec![false; count + 1];
+        leaders[0] = true;
+
+        for (address, (statement, line, _)) in self.instructions.iter().enumerate() {
+            let resolved;
+            let instruction = match statement {
+                Statement::Instruction(instruction) | Statement::LabelByte(instruction, _) => instruction,
+                Statement::LabelOffset(instruction, offset) => {
+                    resolved = Self::resolve_label_offset(instruction, *offset, &self.labels, *line).unwrap_or_else(|_| instruction.clone());
+                    &resolved
+                },
+                Statement::Word(_) => {
+                    leaders[address] = true;
+                    leaders[address + 1] = true;
+                    continue;
+                }
+            };
+
+            match instruction {
+                Instruction::Jump(_) | Instruction::Branch(..) | Instruction::Call(_) => {
+                    leaders[address + 1] = true;
+
+                    if let Ok(word) = instruction.binary(address as u32, &self.labels) {
+                        let target = encoding::address(word) as usize;
+                        if target < count {
+                            leaders[target] = true;
+                        }
+                    }
+                },
+                Instruction::Return | Instruction::Halt => leaders[address + 1] = true,
+                _ => {}
+            }
+        }
+
+        let mut longest: Option<(u32, u32)> = None;
+        let mut start = 0;
+        for address in 1..=count {
+            if !leaders[address] {
+                continue;
+            }
+
+            let is_data = matches!(self.instructions[start].0, Statement::Word(_));
+            let length = (address - start) as u32;
+
+            if !is_data && !longest.is_some_and(|(_, longest_length)| longest_length >= length) {
+                longest = Some((start as u32, length));
+            }
+
+            start = address;
+        }
+
+        longest
+    }
+
+    pub fn size_profile(&self) -> Vec<(String, u32)> {
+        let mut labels: Vec<(&String, u32)> = self.labels
+            .iter()
+            .map(|(name, &address)| (name, address))
+            .collect();
+        labels.sort_by_key(|&(_, address)| address);
+
+        let end = self.instructions.len() as u32;
+
+        // Each label's region ends where the next label at a higher address starts
+        let mut profile: Vec<(String, u32)> = labels
+            .iter()
+            .map(|&(name, address)| {
+                let next_address = labels
+                    .iter()
+                    .map(|&(_, next_address)| next_address)
+                    .find(|&next_address| next_address > address)
+                    .unwrap_or(end);
+
+                (name.clone(), next_address - address)
+            })
+            .collect();
+
+        profile.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        profile
+    }
+
+    pub fn output_byte_len(&self) -> usize {
+        let count = self.config.pad_to.map_or(self.instructions.len(), |size| size.max(self.instructions.len()));
+
+        // For formats whose length depends on the words themselves
+        let machine_code = || self.encode().ok().and_then(|binary| self.pad(binary).ok());
+
+        match self.config.output_format {
+            OutputFormat::Binary => count * size_of::<Word>(),
+            OutputFormat::Text | OutputFormat::Hex => {
+                if count == 0 {
+                    return 0;
+                }
+
+                let digits = match self.config.output_format {
+                    OutputFormat::Hex => (BITS as usize).div_ceil(4),
+                    _ => BITS as usize
+                };
+
+                // Every word is followed by a space or newline, except the last one
+                count * (digits + 1) - 1 + self.embedded_source().len()
+            },
+            OutputFormat::Listing => self.listing(&vec![Word::default(); count], 0).len(),
+            OutputFormat::Report => self.report(&vec![Word::default(); count]).len(),
+            OutputFormat::Container => Container::new(vec![0; count], self.labels.clone()).to_bytes().len(),
+            OutputFormat::SExpr => machine_code().map_or(0, |machine_code| self.sexprs(&machine_code, 0).len()),
+            OutputFormat::Logisim => machine_code().map_or(0, |machine_code| Self::logisim(&machine_code).len()),
+            OutputFormat::PlaygroundJson => machine_code().map_or(0, |machine_code| self.playground_json(&machine_code, 0).len()),
+            OutputFormat::Mif => Self::mif(&vec![Word::default(); count]).len()
+        }
+    }
+    
+    /// Like `assemble`, but as the flat bytes of a raw binary ROM image, with every word in the given byte order
+    pub fn assemble_bytes(&self, big_endian: bool) -> Result<Vec<u8>, Vec<AssemblerError>> {
+        Ok(Self::to_bytes(&self.assemble()?, big_endian))
+    }
+
+    fn to_bytes(machine_code: &[Word], big_endian: bool) -> Vec<u8> {
+        machine_code
+            .iter()
+            .flat_map(|word| if big_endian { word.to_be_bytes() } else { word.to_le_bytes() })
+            .collect()
+    }
+
+    pub fn assemble_to_file(&mut self, path: &str) -> Result<(), Vec<Box<dyn Error>>> {
+        if Path::new(path).is_dir() {
+            return Err(vec![AssemblerError::new(format!("Output path \"{}\" is a directory", path)).into()]);
+        }
+
+        self.check_embed_source()?;
+
+        let assemble_result = self.assemble();
+        match assemble_result {
+            Ok(_) if self.config.check_only => Ok(()),
+            Ok(machine_code) => {
+                self.write_listing(&machine_code)?;
+
+                if !self.is_banked() {
+                    return self.write_machine_code(path, &machine_code, 0);
+                }
+
+                let bank_size = address::MAX_POSSIBLE_COUNT as usize;
+                for (bank, words) in machine_code.chunks(bank_size).enumerate() {
+                    self.write_machine_code(&Self::bank_path(path, bank), words, bank * bank_size)?;
+                }
+
+                Ok(())
+            },
+            Err(errors) => {
+                let errors = errors
+                    .iter()
+                    .map(|error| error.clone().into())
+                    .collect();
+                
+                Err(errors)
+            }
+        }
+    }
+
+    pub fn assemble_to_writer<W: Write>(&self, writer: &mut W) -> Result<(), Vec<Box<dyn Error>>> {
+        // Checking doesn't write anything, so banks don't matter
+        if self.is_banked() && !self.config.check_only {
+            return Err(vec![AssemblerError::new("Programs split into banks can only be written to files".to_string()).into()]);
+        }
+
+        self.check_embed_source()?;
+
+        match self.assemble() {
+            Ok(_) if self.config.check_only => Ok(()),
+            Ok(machine_code) => {
+                self.write_listing(&machine_code)?;
+                self.write_machine_code_to(writer, &machine_code, 0)
+            },
+            Err(errors) => {
+                let errors = errors
+                    .iter()
+                    .map(|error| error.clone().into())
+                    .collect();
+
+                Err(errors)
+            }
+        }
+    }
+
+    fn write_machine_code(&self, path: &str, machine_code: &[Word], start_address: usize) -> Result<(), Vec<Box<dyn Error>>> {
+        let file_result = File::create(path);
+        match file_result {
+            Ok(file) => {
+                let mut output_writer = BufWriter::new(file);
+                self.write_machine_code_to(&mut output_writer, machine_code, start_address)?;
+
+                if let Err(error) = output_writer.flush() {
+                    return Err(vec![error.into()]);
+                }
+
+                Ok(())
+            },
+            Err(error) => {
+                Err(vec![error.into()])
+            }
+        }
+    }
+
+    fn write_machine_code_to<W: Write>(&self, writer: &mut W, machine_code: &[Word], start_address: usize) -> Result<(), Vec<Box<dyn Error>>> {
+        match self.config.output_format {
+            OutputFormat::Binary => {
+                let binary_write = writer.write_all(&Self::to_bytes(machine_code, !self.config.little_endian));
+                if let Err(error) = binary_write {
+                    return Err(vec![error.into()]);
+                }
+            },
+            OutputFormat::Text | OutputFormat::Hex => {
+                for (i, &instruction) in machine_code.iter().enumerate() {
+                    let line = match self.config.output_format {
+                        OutputFormat::Hex => format!("{:0digits$X}", instruction, digits=(BITS as usize).div_ceil(4)),
+                        _ => format!("{:0bits$b}", instruction, bits=BITS as usize)
+                    };
+
+                    let instruction_write = writer.write_all(line.as_bytes());
+                    if let Err(error) = instruction_write {
+                        return Err(vec![error.into()]);
+                    }
+
+                    if i < machine_code.len() - 1 {
+                        let separator = if (i + 1) % self.config.words_per_line.max(1) == 0 { b'\n' } else { b' ' };
+
+                        let line_write = writer.write_all(&[separator]);
+                        if let Err(error) = line_write {
+                            return Err(vec![error.into()]);
+                        }
+                    }
+                }
+
+                let source_write = writer.write_all(self.embedded_source().as_bytes());
+                if let Err(error) = source_write {
+                    return Err(vec![error.into()]);
+                }
+            },
+            OutputFormat::Listing => {
+                let listing = self.listing(machine_code, start_address);
+
+                let listing_write = writer.write_all(listing.as_bytes());
+                if let Err(error) = listing_write {
+                    return Err(vec![error.into()]);
+                }
+            },
+            OutputFormat::Report => {
+                let report = self.report(machine_code);
+
+                let report_write = writer.write_all(report.as_bytes());
+                if let Err(error) = report_write {
+                    return Err(vec![error.into()]);
+                }
+            },
+            OutputFormat::Container => {
+                let container = Container::new(machine_code.to_vec(), self.labels.clone());
+
+                let container_write = writer.write_all(&container.to_bytes());
+                if let Err(error) = container_write {
+                    return Err(vec![error.into()]);
+                }
+            },
+            OutputFormat::SExpr => {
+                let sexprs = self.sexprs(machine_code, start_address);
+
+                let sexpr_write = writer.write_all(sexprs.as_bytes());
+                if let Err(error) = sexpr_write {
+                    return Err(vec![error.into()]);
+                }
+            },
+            OutputFormat::Logisim => {
+                let image = Self::logisim(machine_code);
+
+                let image_write = writer.write_all(image.as_bytes());
+                if let Err(error) = image_write {
+                    return Err(vec![error.into()]);
+                }
+            },
+            OutputFormat::PlaygroundJson => {
+                let json = self.playground_json(machine_code, start_address);
+
+                let json_write = writer.write_all(json.as_bytes());
+                if let Err(error) = json_write {
+                    return Err(vec![error.into()]);
+                }
+            },
+            OutputFormat::Mif => {
+                let mif = Self::mif(machine_code);
+
+                let mif_write = writer.write_all(mif.as_bytes());
+                if let Err(error) = mif_write {
+                    return Err(vec![error.into()]);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The words, labels and the source line of every word (null for ones the assembler inserted or that came from an included file) in one document.
+    // Label names can only contain letters, digits, '_' and '.', so they never need escaping
+    fn playground_json(&self, machine_code: &[Word], start_address: usize) -> String {
+        let words: Vec<String> = machine_code
+            .iter()
+            .map(|word| word.to_string())
+            .collect();
+
+        let symbols: Vec<String> = self.sorted_labels()
+            .into_iter()
+            .map(|(name, address)| format!("\"{}\":{}", name, address))
+            .collect();
+
+        let source_map: Vec<String> = (start_address..start_address + machine_code.len())
+            .map(|address| match self.source_line(address) {
+                Some(line) => line.to_string(),
+                None => "null".to_string()
+            })
+            .collect();
+
+        format!(
+            "{{\"words\":[{}],\"symbols\":{{{}}},\"source_map\":[{}]}}",
+            words.join(","),
+            symbols.join(","),
+            source_map.join(",")
+        )
+    }
+
+    fn listing(&self, machine_code: &[Word], start_address: usize) -> String {
+        let mut defines: Vec<(&String, &String)> = self.defines.iter().collect();
+        defines.sort();
+
+        let mut listing = String::from("// Defines\n");
+        for (name, value) in defines {
+            listing.push_str(&format!("// {} = {}\n", name, value));
+        }
+
+        listing.push_str("// Labels\n");
+        for (name, &address) in self.sorted_labels() {
+            listing.push_str(&format!("// {} = {}\n", name, Self::format_address(address)));
+        }
+
+        listing.push('\n');
+
+        let rows: Vec<String> = machine_code
+            .iter()
+            .enumerate()
+            .map(|(i, &instruction)| {
+                let address = start_address + i;
+                // Padding has no line, like other words the assembler inserts
+                let (line, file) = self.instructions.get(address).map_or((0, 0), |&(_, line, file)| (line, file));
+
+                let location = match file {
+                    0 => format!("Line {}", line),
+                    _ => format!("Line {} in \"{}\"", line, self.included_files[file - 1].path)
+                };
+
+                let row = format!(
+                    "{}  {:0bits$b}{}  // {}",
+                    Self::format_address(address as u32),
+                    instruction,
+                    self.signed_column(instruction),
+                    location,
+                    bits=BITS as usize
+                );
+
+                match self.comments.get(&(file, line)) {
+                    Some(comment) => format!("{}: {}", row, comment),
+                    None => row
+                }
+            })
+            .collect();
+
+        listing.push_str(&rows.join("\n"));
+        listing
+    }
+
+    fn sexprs(&self, machine_code: &[Word], start_address: usize) -> String {
+        let lines: Vec<String> = machine_code
+            .iter()
+            .enumerate()
+            .map(|(i, &word)| match self.instructions.get(start_address + i) {
+                Some((Statement::Instruction(_) | Statement::LabelByte(..) | Statement::LabelOffset(..), _, _)) => sexpr::sexpr(word),
+                // Padding is data as well
+                Some((Statement::Word(_), _, _)) | None => sexpr::word_sexpr(word)
+            })
+            .collect();
+
+        lines.join("\n")
+    }
+
+    fn check_embed_source(&self) -> Result<(), Vec<Box<dyn Error>>> {
+        if self.config.embed_source && !matches!(self.config.output_format, OutputFormat::Text | OutputFormat::Hex) {
+            return Err(vec![AssemblerError::new("The source can only be embedded in text or hexadecimal output".to_string()).into()]);
+        }
+
+        Ok(())
+    }
+
+    // The source as comments, written after the words
+    fn embedded_source(&self) -> String {
+        if !self.config.embed_source {
+            return String::new();
+        }
+
+        self.source_lines
+            .iter()
+            .map(|line| format!("\n// {}", line).trim_end().to_string())
+            .collect()
+    }
+
+    // The word read as two's complement, such as -1 for 0xFFFF, when signed listings are enabled
+    fn signed_column(&self, word: Word) -> String {
+        if !self.config.signed_listing {
+            return String::new();
+        }
+
+        format!("  {:>6}", word as i16)
+    }
+
+    // Every source line, next to the address and word of each statement on it
+    fn source_listing(&self, machine_code: &[Word]) -> String {
+        let mut line_addresses: HashMap<(usize, u32), Vec<usize>> = HashMap::new();
+        for (address, (_, line, file)) in self.instructions.iter().enumerate() {
+            if *line != 0 {
+                line_addresses.entry((*file, *line)).or_default().push(address);
+            }
+        }
+
+        let mut rows = Vec::new();
+        self.source_listing_rows(machine_code, &line_addresses, 0, &self.source_lines, &mut rows);
+
+        rows.join("\n")
+    }
+
+    // Rows for the lines of one file, with each included file right after its #include line
+    fn source_listing_rows(&self, machine_code: &[Word], line_addresses: &HashMap<(usize, u32), Vec<usize>>, file: usize, lines: &[String], rows: &mut Vec<String>) {
+        let digits = (BITS as usize).div_ceil(4);
+        let empty_width = Self::format_address(0).len() + 2 + digits + self.signed_column(0).len();
+
+        for (i, source) in lines.iter().enumerate() {
+            let line = i as u32 + 1;
+
+            match line_addresses.get(&(file, line)) {
+                Some(addresses) => {
+                    for (j, &address) in addresses.iter().enumerate() {
+                        let row = format!(
+                            "{}  {:0digits$X}{}  {}",
+                            Self::format_address(address as u32),
+                            machine_code[address],
+                            self.signed_column(machine_code[address]),
+                            if j == 0 { source.as_str() } else { "" },
+                            digits = digits
+                        );
+
+                        rows.push(row.trim_end().to_string());
+                    }
+                },
+                None => {
+                    let row = format!("{:width$}  {}", "", source, width = empty_width);
+                    rows.push(row.trim_end().to_string());
+                }
+            }
+
+            for (index, included) in self.included_files.iter().enumerate() {
+                if included.parent == (file, line) {
+                    self.source_listing_rows(machine_code, line_addresses, index + 1, &included.lines, rows);
+                }
+            }
+        }
+    }
+
+    // Logisim memory image, runs of the same word are written as "count*word"
+    fn logisim(machine_code: &[Word]) -> String {
+        let mut image = String::from("v2.0 raw");
+
+        let mut i = 0;
+        while i < machine_code.len() {
+            let word = machine_code[i];
+            let run = machine_code[i..].iter().take_while(|&&other| other == word).count();
+
+            if run > 1 {
+                image.push_str(&format!("\n{}*{:x}", run, word));
+            } else {
+                image.push_str(&format!("\n{:x}", word));
+            }
+
+            i += run;
+        }
+
+        image
+    }
+
+    // Memory initialization file for FPGA tools such as Quartus, the rest of the ROM is filled with zeros
+    fn mif(machine_code: &[Word]) -> String {
+        let depth = machine_code.len().max(address::MAX_POSSIBLE_COUNT as usize);
+
+        let mut mif = format!("WIDTH={};\nDEPTH={};\n\nADDRESS_RADIX=UNS;\nDATA_RADIX=BIN;\n\nCONTENT BEGIN\n", BITS, depth);
+        for (address, word) in machine_code.iter().enumerate() {
+            mif.push_str(&format!("\t{} : {:0bits$b};\n", address, word, bits=BITS as usize));
+        }
+
+        if machine_code.len() < depth {
+            mif.push_str(&format!("\t[{}..{}] : {:0bits$b};\n", machine_code.len(), depth - 1, 0, bits=BITS as usize));
+        }
+
+        mif.push_str("END;\n");
+        mif
+    }
+
+    fn report(&self, machine_code: &[Word]) -> String {
+        let words: Vec<String> = machine_code
+            .iter()
+            .map(|instruction| format!("{:0bits$b}", instruction, bits=BITS as usize))
+            .collect();
+
+        let mut report = words.join("\n");
+
+        report.push_str("\n\n// Symbols\n");
+        for (name, &address) in self.sorted_labels() {
+            report.push_str(&format!("// {} = {}\n", name, Self::format_address(address)));
+        }
+
+        report.push_str("\n// Stats\n");
+        report.push_str(&format!("// {}", self.usage()));
+
+        report
+    }
+
+    fn sorted_labels(&self) -> Vec<(&String, &u32)> {
+        let mut labels: Vec<(&String, &u32)> = self.labels.iter().collect();
+        labels.sort_by(|a, b| a.1.cmp(b.1).then(a.0.cmp(b.0)));
+        labels
+    }
+
+    fn usage(&self) -> String {
+        format!(
+            "{} out of {} instructions used ({:.1}%)",
+            Self::with_commas(self.instructions.len() as u32),
+            Self::with_commas(address::MAX_POSSIBLE_COUNT),
+            self.instructions.len() as f32 * 100.0 / address::MAX_POSSIBLE_COUNT as f32
+        )
+    }
+
+    fn is_banked(&self) -> bool {
+        self.config.banks && self.instructions.len() > address::MAX_POSSIBLE_COUNT as usize
+    }
+
+    fn bank_path(path: &str, bank: usize) -> String {
+        let path = Path::new(path);
+        let bank_path = match path.extension() {
+            Some(extension) => path.with_extension(format!("{}.{}", bank, extension.to_string_lossy())),
+            None => path.with_extension(bank.to_string())
+        };
+
+        bank_path.to_string_lossy().into_owned()
+    }
+
+    fn label_reference(instruction: &Instruction) -> Option<&str> {
+        match instruction {
+            Instruction::Jump(Location::Label(label)) |
+            Instruction::Branch(_, Location::Label(label)) |
+            Instruction::Call(Location::Label(label)) => Some(label.as_str()),
+            _ => None
+        }
+    }
+
+    // The encoder masks the address field, so a label past it would silently jump somewhere else
+    fn check_target(instruction: &Instruction, labels: &Labels, line: u32) -> Result<(), AssemblerError> {
+        if let Some(label) = Self::label_reference(instruction) {
+            if let Some(&label_address) = labels.get(label) {
+                if label_address > encoding::ADDRESS_MAX as u32 {
+                    return Err(AssemblerError::new_line(format!("Label \"{}\" is at address {}, but jumps can only reach addresses up to {}", label, label_address, encoding::ADDRESS_MAX), line));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Address::new allows every address in the ROM, but jmp, brh and cal only have a 10-bit field
+    fn checked_address(value: u32, source: &str, line: u32) -> Result<Address, AssemblerError> {
+        if value > encoding::ADDRESS_MAX as u32 {
+            let origin = if source != value.to_string() {
+                format!(" (from \"{}\")", source)
+            } else {
+                String::new()
+            };
+
+            let description = format!("Address {}{} is past the last address jumps can reach ({})", value, origin, encoding::ADDRESS_MAX);
+            return Err(if line == 0 { AssemblerError::new(description) } else { AssemblerError::new_line(description, line) });
+        }
+
+        Address::new(value).map_err(|error| Self::instruction_error(&error, line))
+    }
+
+    // Offsets such as "+3" only have a target once the instruction has its final address, which the 10-bit field would wrap
+    fn check_offset_target(instruction: &Instruction, binary: Word, address: usize, line: u32) -> Result<(), AssemblerError> {
+        if !matches!(instruction, Instruction::Jump(Location::Offset(_)) | Instruction::Branch(_, Location::Offset(_)) | Instruction::Call(Location::Offset(_))) {
+            return Ok(());
+        }
+
+        // Offsets reach at most 8 addresses away, so the nearest address with the encoded bits is the real target
+        let field_size = encoding::ADDRESS_MAX as i64 + 1;
+        let distance = (encoding::address(binary) as i64 - address as i64).rem_euclid(field_size);
+        let target = address as i64 + if distance > field_size / 2 { distance - field_size } else { distance };
+
+        if target < 0 || target > encoding::ADDRESS_MAX as i64 {
+            return Err(AssemblerError::new_line(format!("Offset jumps to address {}, which is outside of 0 to {}", target, encoding::ADDRESS_MAX), line));
+        }
+
+        Ok(())
+    }
+
+    // Labels can only be referenced from their own bank, including with an offset such as "loop+2"
+    fn check_bank(&self, instruction: &Instruction, address: usize, line: u32) -> Result<(), AssemblerError> {
+        let bank_size = address::MAX_POSSIBLE_COUNT as usize;
+        let bank = address / bank_size;
+
+        if let Some(label) = Self::label_reference(instruction) {
+            if let Some(&label_address) = self.labels.get(label) {
+                let label_bank = label_address as usize / bank_size;
+                if label_bank != bank {
+                    return Err(AssemblerError::new_line(format!("Label \"{}\" is in bank {}, but is referenced from bank {}", label, label_bank, bank), line));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn binary_banked(&self, instruction: &Instruction, address: usize, line: u32, bank_labels: &[Labels]) -> Result<Word, AssemblerError> {
+        let bank_size = address::MAX_POSSIBLE_COUNT as usize;
+        let bank = address / bank_size;
+
+        self.check_bank(instruction, address, line)?;
+
+        match instruction.binary((address % bank_size) as u32, &bank_labels[bank]) {
+            Ok(binary) => Ok(binary),
+            Err(error) => Err(Self::instruction_error(&error, line))
+        }
+    }
+
+    // Makes sure the encoder put the right opcode in, and no stray bits in instructions without operands
+    fn verify_encoding(instruction: &Instruction, binary: Word, address: usize, line: u32) -> Result<(), AssemblerError> {
+        let (mnemonic, has_operands) = match instruction {
+            Instruction::NoOperation => ("nop", false),
+            Instruction::Halt => ("hlt", false),
+            Instruction::Addition(..) => ("add", true),
+            Instruction::Subtraction(..) => ("sub", true),
+            Instruction::BitwiseNOR(..) => ("nor", true),
+            Instruction::BitwiseAND(..) => ("and", true),
+            Instruction::BitwiseXOR(..) => ("xor", true),
+            Instruction::RightShift(..) => ("rsh", true),
+            Instruction::LoadImmediate(..) => ("ldi", true),
+            Instruction::AddImmediate(..) => ("adi", true),
+            Instruction::Jump(_) => ("jmp", true),
+            Instruction::Branch(..) => ("brh", true),
+            Instruction::Call(_) => ("cal", true),
+            Instruction::Return => ("ret", false),
+            Instruction::MemoryLoad(..) => ("lod", true),
+            Instruction::MemoryStore(..) => ("str", true)
+        };
+
+        let opcode = encoding::MNEMONICS.iter().position(|&other| other == mnemonic).unwrap() as Word;
+
+        let valid = if has_operands {
+            encoding::opcode(binary) == opcode
+        } else {
+            binary == opcode << 12
+        };
+
+        if valid {
+            return Ok(());
+        }
+
+        let description = format!("\"{}\" at address {} was encoded as 0x{:04X}, which isn't a valid \"{}\"", mnemonic, address, binary, mnemonic);
+        if line == 0 {
+            Err(AssemblerError::new(description))
+        } else {
+            Err(AssemblerError::new_line(description, line))
+        }
+    }
+
+    fn instruction_error(error: &AssemblyError, line: u32) -> AssemblerError {
+        if line == 0 {
+            AssemblerError::from_assembly_error(error)
+        } else {
+            AssemblerError::from_assembly_error_line(error, line)
+        }
+    }
+
+    fn parse_u32(str: &str) -> Result<u32, Box<dyn Error>> {
+        let str = str.replace('_', "");
+
+        if str.starts_with("0x") {
+            Ok(u32::from_str_radix(&str[2..], 16)?)
+        } else if str.starts_with("0b") {
+            Ok(u32::from_str_radix(&str[2..], 2)?)
+        } else if str.starts_with("0o") {
+            Ok(u32::from_str_radix(&str[2..], 8)?)
+        } else {
+            Ok(str.parse()?)
+        }
+    }
+
+    fn parse_i32(str: &str) -> Result<i32, Box<dyn Error>> {
+        let str = str.replace('_', "");
+
+        if str.starts_with("0x") {
+            Ok(i32::from_str_radix(&str[2..], 16)?)
+        } else if str.starts_with("0b") {
+            Ok(i32::from_str_radix(&str[2..], 2)?)
+        } else if str.starts_with("0o") {
+            Ok(i32::from_str_radix(&str[2..], 8)?)
+        } else {
+            Ok(str.parse()?)
+        }
+    }
+
+    // A leading sign is part of a number, such as "-1" or "+3" in "jmp +3"
+    fn is_expression(value: &str) -> bool {
+        if value.starts_with('\'') {
+            return false;
+        }
+
+        value.starts_with('(') || value.chars().skip(1).any(|c| matches!(c, '+' | '-' | '*' | '/' | '(' | ')'))
+    }
+
+    fn evaluate(&mut self, expression: &str) -> Result<i32, Box<dyn Error>> {
+        let line = self.line;
+        let result = expression::evaluate(expression, &mut |value| self.expression_value(value));
+
+        // Labels later in the file aren't known yet, and a failed statement would take up no address and move every label after it
+        if self.first_pass {
+            return Ok(result.ok().and_then(|value| i32::try_from(value).ok()).unwrap_or(0));
+        }
+
+        match result {
+            Ok(value) => match i32::try_from(value) {
+                Ok(value) => Ok(value),
+                Err(_) => Err(AssemblerError::new_line(format!("Expression \"{}\" is out of range ({})", expression, value), line).into())
+            },
+            Err(description) => Err(AssemblerError::new_line(description, line).into())
+        }
+    }
+
+    // Like evaluate, but for immediates and offsets, which keep a label address as the number it is before the layout
+    fn evaluate_number(&mut self, expression: &str) -> Result<i32, Box<dyn Error>> {
+        self.read_label = false;
+        let value = self.evaluate(expression)?;
+
+        if self.read_label && !self.first_pass {
+            self.label_numbers.push((self.line, self.file));
+        }
+
+        Ok(value)
+    }
+
+    // Numbers, defines and labels
+    fn expression_value(&mut self, value: &str) -> Result<i64, String> {
+        let parse_error = match Self::parse_i32(value) {
+            Ok(number) => return Ok(number as i64),
+            Err(error) => error
+        };
+
+        if let Some(definition) = self.resolve_define(value).map_err(|error| error.description)? {
+            return match Self::parse_i32(&definition) {
+                Ok(number) => Ok(number as i64),
+                Err(_) => Err(format!("Define \"{}\" is \"{}\", which isn't a number", value, definition))
+            };
+        }
+
+        if let Some(&address) = self.labels.get(value).or_else(|| self.first_pass_labels.get(value)) {
+            self.used_labels.insert(value.to_string());
+            self.read_label = true;
+            return Ok(address as i64);
+        }
+
+        if value.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(format!("Failed to parse \"{}\": {}", value, parse_error));
+        }
+
+        Err(format!("\"{}\" isn't a define or a label", value))
+    }
+
+    fn is_overflow(error: &(dyn Error + 'static)) -> bool {
+        error
+            .downcast_ref::<ParseIntError>()
+            .is_some_and(|error| matches!(error.kind(), IntErrorKind::PosOverflow | IntErrorKind::NegOverflow))
+    }
+
+    fn literal_error(&self, context: &str, literal: &str, error: Box<dyn Error>, max: i64) -> Box<dyn Error> {
+        if Self::is_overflow(error.as_ref()) {
+            return AssemblerError::new_line(format!("Literal \"{}\" is too large for {} (maximum is {})", literal, context, max), self.line).into();
+        }
+
+        AssemblerError::new_line(format!("Failed to parse {} \"{}\": {}", context, literal, error), self.line).into()
+    }
+
+    fn get_data(&self, value: &str, bits: u32) -> Result<Word, Box<dyn Error>> {
+        let result = Self::parse_i32(value);
+
+        match result {
+            Ok(num) => {
+                let min = -(1 << (bits - 1));
+                let max = (1 << bits) - 1;
+
+                if num < min || num > max {
+                    return Err(AssemblerError::new_line(format!("Value \"{}\" doesn't fit in {} bits ({} to {})", value, bits, min, max), self.line).into());
+                }
+
+                Ok((num & max) as Word)
+            },
+            Err(error) => {
+                Err(self.literal_error("value", value, error, (1 << bits) - 1))
+            }
+        }
+    }
+
+    // Only stores where the base register is r0 or was just loaded with ldi are known
+    fn record_constant_store(&mut self, name: &str, args: &[&str]) {
+        if name != "str" {
+            return;
+        }
+
+        let base = match Self::register_index(args[1]) {
+            Some(0) => Some(0),
+            Some(register) => self.loaded_immediates.get(&register).map(|&(value, _, _, _)| value),
+            None => None
+        };
+
+        if let (Some(base), Ok(offset)) = (base, Self::parse_i32(args[3])) {
+            self.constant_stores.push((base + offset, self.line, self.file));
+        }
+    }
+
+    // The register holds a label address now, which isn't known until the layout is done
+    fn forget_loaded_immediate(&mut self, register: &str) {
+        if let Some(register) = Self::register_index(register) {
+            self.loaded_immediates.remove(&register);
+        }
+    }
+
+    // Data memory is separate on the BatPU, but this catches stores meant for hardware where it isn't
+    fn check_constant_stores(&mut self) {
+        if !self.config.warnings {
+            return;
+        }
+
+        let program_size = self.instructions.len() as i32;
+
+        let mut warnings = Vec::new();
+        for &(address, line, file) in &self.constant_stores {
+            if (0..program_size).contains(&address) && address < PORTS_START {
+                let warning = AssemblerError::new_line(format!(
+                    "\"str\" to address {} is inside the program (addresses 0 to {}), which would overwrite code if memory were shared",
+                    address,
+                    program_size - 1
+                ), line);
+
+                warnings.push(self.in_file(warning, file));
+            }
+        }
+
+        self.warnings.append(&mut warnings);
+    }
+
+    // Suggests a character literal for numbers loaded with ldi and then written to the character display
+    fn check_character_hint(&mut self, name: &str, args: &[&str]) {
+        let destination = match name {
+            "ldi" => {
+                if let (Some(register), Ok(value)) = (Self::register_index(args[1]), Self::parse_i32(args[2])) {
+                    self.loaded_immediates.insert(register, (value, args[2].to_string(), self.line, self.file));
+                    return;
+                }
+
+                args.get(1)
+            },
+            "str" => {
+                let port = Self::register_index(args[1])
+                    .and_then(|register| self.loaded_immediates.get(&register))
+                    .zip(Self::parse_i32(args[3]).ok())
+                    .map(|(&(address, _, _, _), offset)| address + offset);
+
+                let value_register = Self::register_index(args[2]);
+                if port != Some(CHAR_DISP_PORT) {
+                    return;
+                }
+
+                if let Some((value, immediate, line, file)) = value_register.and_then(|register| self.loaded_immediates.remove(&register)) {
+                    let character = usize::try_from(value).ok().and_then(|index| CHARACTERS.get(index));
+
+                    if let Some(character) = character {
+                        let warning = AssemblerError::new_line(format!("Immediate \"{}\" could be written as the character literal '{}'", immediate, character), line);
+                        let warning = self.in_file(warning, file);
+                        self.warnings.push(warning);
+                    }
+                }
+
+                return;
+            },
+            "cal" => {
+                self.loaded_immediates.clear();
+                return;
+            },
+            "add" | "sub" | "nor" | "and" | "xor" | "or" | "nand" => args.get(3),
+            "rsh" | "lod" | "mov" | "lsh" | "not" | "neg" => args.get(2),
+            "adi" | "inc" | "dec" => args.get(1),
+            _ => None
+        };
+
+        if let Some(register) = destination.and_then(|register| Self::register_index(register)) {
+            self.loaded_immediates.remove(&register);
+        }
+    }
+
+    // Registers can also be aliases, such as "counter" after "#define counter r7",
+    // which were already substituted (through every define in the chain) by parse_piece
+    fn get_register(&mut self, register: &str) -> Result<Register, Box<dyn Error>> {
+        self.argument = Some(register.to_string());
+
+        if !register.starts_with('r') {
+            return Err(self.register_error(register, format!("Register \"{}\" must start with a lowercase 'r'", register)));
+        }
+
+        let full_register = register;
+        let register = &register[1..];
+
+        if self.config.style_lints && register.len() > 1 && register.starts_with('0') {
+            self.lint(format!("Register \"r{}\" has leading zeros", register));
+        }
+        let result = register.parse::<u32>();
+
+        match result {
+            Ok(num) => {
+                // Macros can be where reserved registers are meant to be used, such as a calling convention
+                let in_macro = !self.expanding_macros.is_empty() && self.config.allow_reserved_in_macros;
+                if self.reserved_registers.contains(&num) && !in_macro {
+                    return Err(self.register_error(full_register, format!("Register \"r{}\" is reserved", num)));
+                }
+
+                let result = Register::new(num);
+                match result {
+                    Ok(register) => Ok(register),
+                    Err(error) => {
+                        Err(self.register_error(full_register, error.description))
+                    }
+                }
+            },
+            Err(error) => {
+                Err(self.register_error(full_register, format!("Failed to parse register \"{}\": {}", register, error)))
+            }
+        }
+    }
+
+    fn register_error(&self, register: &str, description: String) -> Box<dyn Error> {
+        match self.piece_defines.get(register) {
+            Some(alias) => AssemblerError::new_line(format!("{} (from \"{}\")", description, alias), self.line).into(),
+            None => AssemblerError::new_line(description, self.line).into()
+        }
+    }
+
+    fn register_index(register: &str) -> Option<u32> {
+        register.strip_prefix('r')?.parse().ok()
+    }
+
+    fn get_label_byte(immediate: &str) -> Option<LabelByte> {
+        let (high, label) = if let Some(label) = immediate.strip_prefix("lo(") {
+            (false, label)
+        } else if let Some(label) = immediate.strip_prefix("hi(") {
+            (true, label)
+        } else {
+            return None;
+        };
+
+        let label = label.strip_suffix(')')?;
+        Some(LabelByte { label: label.trim().to_string(), high })
+    }
+
+    fn get_character(&self, char: char) -> Result<u32, AssemblerError> {
+        match CHARACTERS.iter().position(|&c| c == char) {
+            Some(index) => Ok(index as u32),
+            None => Err(AssemblerError::new_line(format!("Character \"{}\" is not supported, you can only use ones in \"{}\"", char, CHARACTERS.iter().collect::<String>()), self.line))
+        }
+    }
+
+    // A single character, or an escape sequence such as '\'' for characters that would end the literal
+    fn get_literal_character(&self, literal: &str) -> Result<char, AssemblerError> {
+        let mut chars = literal.chars();
+
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('\\'), Some(escaped @ ('\\' | '\'' | '"')), None) => Ok(escaped),
+            (Some('\\'), Some(escaped @ ('n' | 't' | 'r' | '0')), None) => {
+                Err(AssemblerError::new_line(format!("Escape sequence \"\\{}\" has no character on the character display", escaped), self.line))
+            },
+            (Some('\\'), Some(escaped), None) => {
+                Err(AssemblerError::new_line(format!("Unknown escape sequence \"\\{}\"", escaped), self.line))
+            },
+            (Some(char), None, _) => Ok(char),
+            _ => Err(AssemblerError::new_line(format!("Immediate \"{}\" must only contain a single character", literal), self.line))
+        }
+    }
+
+    fn get_immediate(&mut self, immediate: &str) -> Result<Immediate, Box<dyn Error>> {
+        self.argument = Some(immediate.to_string());
+
+        if Self::is_expression(immediate) {
+            let value = self.evaluate_number(immediate)?;
+            return Ok(Self::checked_immediate(value, immediate, self.line)?);
+        }
+
+        if immediate.starts_with("'") {
+            if immediate.len() < 2 || !immediate.ends_with("'") {
+                return Err(AssemblerError::new_line(format!("Immediate \"{}\" must end with ''", immediate), self.line).into());
+            }
+
+            let char = self.get_literal_character(&immediate[1..immediate.len() - 1])?;
+            return Ok(Immediate::new(self.get_character(char)?));
+        }
+
+        let result = Self::parse_i32(immediate);
+
+        match result {
+            Ok(num) => Ok(Self::checked_immediate(num, immediate, self.line)?),
+            Err(error) => {
+                Err(self.literal_error("immediate", immediate, error, u8::MAX as i64))
+            }
+        }
+    }
+
+    // Both signed (-128 to -1) and unsigned (0 to 255) values fit in the 8-bit field
+    fn checked_immediate(value: i32, source: &str, line: u32) -> Result<Immediate, AssemblerError> {
+        if !(IMMEDIATE_MIN..=IMMEDIATE_MAX).contains(&value) {
+            let origin = if source != value.to_string() {
+                format!(" (from \"{}\")", source)
+            } else {
+                String::new()
+            };
+
+            return Err(AssemblerError::new_line(format!("Immediate {}{} is out of range, it must be between {} and {}", value, origin, IMMEDIATE_MIN, IMMEDIATE_MAX), line));
+        }
+
+        Ok(Immediate::new_signed(value))
+    }
+
+    // "loop+3" or "loop - 1", a label (that isn't a define) with a constant offset
+    fn get_label_offset(&self, location: &str) -> Option<(String, i32)> {
+        let index = location.rfind(['+', '-']).filter(|&index| index > 0)?;
+        let (label, offset) = location.split_at(index);
+
+        let label = label.trim();
+        let (label, forced) = match label.strip_prefix('@') {
+            Some(label) => (label, true),
+            None => (label, false)
+        };
+
+        // "@5loop+1" can start with a digit, since the "@" already makes it a label
+        let is_name = !label.is_empty()
+            && (forced || label.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.'))
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+
+        if !is_name || (!forced && self.defines.contains_key(label)) {
+            return None;
+        }
+
+        let offset = Self::parse_i32(&offset.replace(char::is_whitespace, "")).ok()?;
+        Some((label.to_string(), offset))
+    }
+
+    fn get_location(&mut self, location: &str) -> Result<Location, Box<dyn Error>> {
+        self.argument = Some(location.to_string());
+
+        if Self::is_expression(location) {
+            let value = self.evaluate(location)?;
+
+            return match u32::try_from(value) {
+                Ok(value) => Ok(Location::Address(Self::checked_address(value, location, self.line)?)),
+                Err(_) => Err(AssemblerError::new_line(format!("Address {} (from \"{}\") can't be negative", value, location), self.line).into())
+            };
+        }
+
+        // "@name" always refers to a label, even if it looks like a number or a define
+        if let Some(label) = location.strip_prefix('@') {
+            if label.is_empty() {
+                return Err(AssemblerError::new_line("Label reference \"@\" is missing a name".to_string(), self.line).into());
+            }
+
+            return Ok(Location::Label(label.to_string()));
+        }
+
+        let add = location.starts_with('+');
+        let sub = location.starts_with('-');
+
+        if add || sub {
+            let result = Self::parse_u32(&location[1..]);
+            return match result {
+                Ok(num) => {
+                    let num = if add {
+                        num as i32
+                    } else if sub {
+                        -(num as i32)
+                    } else {
+                        return Err(AssemblerError::new_line(format!("Unknown location \"{}\"", location),  self.line).into());
+                    };
+                    
+                    Ok(Location::Offset(Offset::new(num)?))
+                },
+                Err(error) => {
+                    Err(self.literal_error("address offset", location, error, address::MAX_VALUE as i64))
+                }
+            }
+        }
+
+        let result = Self::parse_u32(location);
+        match result {
+            Ok(num) => Ok(Location::Address(Self::checked_address(num, location, self.line)?)),
+            Err(error) => {
+                if Self::is_overflow(error.as_ref()) {
+                    return Err(self.literal_error("address", location, error, address::MAX_VALUE as i64));
+                }
+
+                Ok(Location::Label(location.to_string()))
+            }
+        }
+    }
+
+    fn get_condition(&mut self, condition: &str) -> Result<Condition, Box<dyn Error>> {
+        self.argument = Some(condition.to_string());
+
+        match encoding::condition_index(condition) {
+            Some(0) => Ok(Condition::Zero),
+            Some(1) => Ok(Condition::NotZero),
+            Some(2) => Ok(Condition::Carry),
+            Some(_) => Ok(Condition::NotCarry),
+            None => Err(AssemblerError::new_line(format!("Unknown condition: \"{}\"", condition), self.line).into())
+        }
+    }
+
+    fn get_offset(&mut self, offset: &str, source: &str) -> Result<Offset, Box<dyn Error>> {
+        self.argument = Some(offset.to_string());
+
+        let result = if Self::is_expression(offset) {
+            Ok(self.evaluate_number(offset)?)
+        } else {
+            Self::parse_i32(offset)
+        };
+        match result {
+            Ok(num) => {
+                let result = Offset::new(num);
+                match result {
+                    Ok(offset) => Ok(offset),
+                    Err(_) => {
+                        let origin = if source != offset {
+                            format!(" (from \"{}\")", source)
+                        } else {
+                            String::new()
+                        };
+
+                        Err(AssemblerError::new_line(format!("Offset {}{} is out of range, it must be between {} and {}", num, origin, OFFSET_MIN, OFFSET_MAX), self.line).into())
+                    }
+                }
+            },
+            Err(error) => {
+                Err(self.literal_error("offset", offset, error, OFFSET_MAX as i64))
+            }
+        }
+    }
+    
+    fn format_address(address: u32) -> String {
+        format!("0x{:04X}", address)
+    }
+
+    fn join_with_and(items: &[&str]) -> String {
+        match items.len() {
+            0 => String::new(),
+            1 => items[0].to_string(),
+            2 => format!("{} and {}", items[0], items[1]),
+            _ => {
+                let all_but_last = &items[..items.len() - 1];
+                let last = items[items.len() - 1];
+                
+                format!("{} and {}", all_but_last.join(", "), last)
+            }
+        }
+    }
+    
+    fn with_commas(mut number: u32) -> String {
+        if number == 0 {
+            return "0".to_string();
+        }
+        
+        let mut result = String::new();
+        let mut digits = 0;
+        
+        while number > 0 {
+            if digits > 0 && digits % 3 == 0 {
+                result.push(',');
+            }
+            
+            let digit = (number % 10) as u8 + b'0';
+            result.push(digit as char);
+            
+            number /= 10;
+            digits += 1;
+        }
+        
+        result.chars().rev().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa_revision::IsaRevision;
+
+    fn parsed(config: AssemblerConfig, source: &str) -> Result<Assembler, Vec<String>> {
+        let mut assembler = Assembler::new(config);
+        match assembler.parse(source) {
+            Ok(()) => Ok(assembler),
+            Err(errors) => Err(errors.iter().map(|error| error.to_string()).collect())
+        }
+    }
+
+    fn assemble_with(config: AssemblerConfig, source: &str) -> Result<Vec<Word>, Vec<String>> {
+        let assembler = parsed(config, source)?;
+        assembler.assemble().map_err(|errors| errors.iter().map(|error| error.to_string()).collect())
+    }
+
+    fn assemble(source: &str) -> Vec<Word> {
+        assemble_with(AssemblerConfig::default(), source).unwrap()
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        assemble_with(AssemblerConfig::default(), source).unwrap_err()
+    }
+
+    #[test]
+    fn assemble_writes_nothing() {
+        let path = std::env::temp_dir().join("batpu_assemble_writes_nothing.lst");
+        let _ = fs::remove_file(&path);
+
+        let config = AssemblerConfig {
+            listing_output: Some(path.to_string_lossy().into_owned()),
+            ..AssemblerConfig::default()
+        };
+
+        assert_eq!(assemble_with(config, "hlt"), Ok(vec![0x1000]));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn continued_instruction() {
+        assert_eq!(assemble("add r1 \\\n    r2 r3\nhlt"), vec![0x2123, 0x1000]);
+    }
+
+    #[test]
+    fn backslash_in_comment_does_not_continue() {
+        assert_eq!(assemble("ldi r1 5 // note \\\nldi r2 6"), vec![0x8105, 0x8206]);
+    }
+
+    #[test]
+    fn mnemonics_are_case_insensitive() {
+        assert_eq!(assemble("LDI r1 5\nInc r1\nHLT"), assemble("ldi r1 5\ninc r1\nhlt"));
+    }
+
+    #[test]
+    fn macro_named_after_instruction() {
+        assert_eq!(errors(".macro LDI a"), vec!["[Line 1] Macro \"LDI\" already exists".to_string()]);
+    }
+
+    #[test]
+    fn parameters_skip_literals_and_comments() {
+        assert_eq!(assemble(".macro put A\nldi A 'A' // A\n.endmacro\nput r1"), vec![0x8141]);
+    }
+
+    #[test]
+    fn columns_only_on_lines_as_written() {
+        let unterminated = |position: &str| vec![format!("[Line {}] Unterminated character/string literal", position)];
+
+        assert_eq!(errors("ldi r1 'A"), unterminated("1:8"));
+        assert_eq!(errors("ldi\u{a0}r1 'A"), unterminated("1"));
+        assert_eq!(errors("ldi r1 \\\n'A"), unterminated("1"));
+        assert_eq!(errors(".macro m\nldi r1 'A\n.endmacro\nm"), vec!["[Line 4] In macro \"m\":\n[Line 4] Unterminated character/string literal".to_string()]);
+    }
+
+    #[test]
+    fn or_into_r0() {
+        assert_eq!(assemble_with(strict(), "or r1 r2 r0\nhlt").unwrap_err(), vec!["[Line 1] \"or\" into r0 does nothing, since writes to r0 are discarded".to_string()]);
+    }
+
+    #[test]
+    fn expand_stops_after_expansion() {
+        let config = AssemblerConfig {
+            preprocess_only: true,
+            warnings: true,
+            ..AssemblerConfig::default()
+        };
+
+        let assembler = parsed(config, "#define VALUE 5\nldi r1 VALUE").unwrap();
+        assert_eq!(assembler.expanded_source(), "ldi r1 5\n");
+        assert!(assembler.warnings().is_empty());
+    }
+
+    #[test]
+    fn pad_with_word() {
+        let config = AssemblerConfig {
+            pad_to: Some(4),
+            pad_word: 0xFFFF,
+            ..AssemblerConfig::default()
+        };
+
+        assert_eq!(assemble_with(config.clone(), "hlt"), Ok(vec![0x1000, 0xFFFF, 0xFFFF, 0xFFFF]));
+        assert_eq!(assemble_with(config, "nop\nnop\nnop\nnop\nhlt"), Err(vec!["Program takes up 5 words, more than the 4 it should be padded to".to_string()]));
+    }
+
+    #[test]
+    fn offset_boundaries() {
+        assert!(errors("lod r1 r2 -9")[0].contains("Offset -9 is out of range"));
+        assert_eq!(assemble("lod r1 r2 -8")[0] & 0b1111, 0b1000);
+        assert_eq!(assemble("str r1 r2 7")[0] & 0b1111, 0b0111);
+        assert!(errors("str r1 r2 8")[0].contains("Offset 8 is out of range"));
+    }
+
+    #[test]
+    fn address_jumps_with_pins() {
+        assert_eq!(errors("jmp +1\n.pin 4\nhlt\n.endpin"), vec!["[Line 1] Jumps to an address or offset can't be used together with \".pin\", use a label instead".to_string()]);
+    }
+
+    #[test]
+    fn label_immediates_with_layout() {
+        let source = "ldi r1 table+1\nhlt\ntable:\n.word 5";
+        assert_eq!(assemble(source), vec![0x8103, 0x1000, 5]);
+
+        let pinned = format!("{}\n.pin 8\nhlt\n.endpin", source);
+        assert_eq!(errors(&pinned), vec!["[Line 1] Label addresses in immediates and offsets can't be used together with \".pin\"".to_string()]);
+
+        let config = AssemblerConfig {
+            interleave_nops: true,
+            ..AssemblerConfig::default()
+        };
+
+        assert_eq!(assemble_with(config, source).unwrap_err(), vec!["[Line 1] Label addresses in immediates and offsets can't be used together with --interleave-nops".to_string()]);
+    }
+
+    #[test]
+    fn region_length_from_labels() {
+        let source = "#define LEN table_end - table_start\nldi r1 LEN\nhlt\ntable_start:\n.word 1 2 3\ntable_end:";
+        assert_eq!(assemble(source), vec![0x8103, 0x1000, 1, 2, 3]);
+    }
+
+    fn strict() -> AssemblerConfig {
+        AssemblerConfig {
+            warnings: true,
+            warnings_as_errors: true,
+            ..AssemblerConfig::default()
+        }
+    }
+
+    #[test]
+    fn org_padding_is_reachable() {
+        assert!(assemble_with(strict(), "jmp main\n.org 16\nmain:\nhlt").is_ok());
+    }
+
+    #[test]
+    fn entry_label_is_used() {
+        assert!(assemble_with(strict(), "main:\nhlt").is_ok());
+    }
+
+    #[test]
+    fn unused_label() {
+        assert_eq!(
+            assemble_with(strict(), "hlt\ndone:\nhlt").unwrap_err(),
+            vec!["[Line 2] Label \"done\" is never used".to_string()]
+        );
+    }
+
+    #[test]
+    fn label_byte_replaces_loaded_immediate() {
+        assert!(assemble_with(strict(), "ldi r1 2\nldi r1 lo(x)\nstr r1 r2 0\nhlt\nx:").is_ok());
+    }
+
+    #[test]
+    fn output_byte_len_matches_output() {
+        let formats = [
+            OutputFormat::Binary,
+            OutputFormat::Text,
+            OutputFormat::Hex,
+            OutputFormat::Listing,
+            OutputFormat::Report,
+            OutputFormat::Container,
+            OutputFormat::SExpr,
+            OutputFormat::Logisim,
+            OutputFormat::PlaygroundJson,
+            OutputFormat::Mif
+        ];
+
+        for format in formats {
+            let config = AssemblerConfig {
+                output_format: format,
+                ..AssemblerConfig::default()
+            };
+
+            let assembler = parsed(config, "start:\nldi r1 5\njmp start").unwrap();
+
+            let mut output = Vec::new();
+            assembler.assemble_to_writer(&mut output).unwrap();
+            assert_eq!(assembler.output_byte_len(), output.len(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn banks() {
+        let count = address::MAX_POSSIBLE_COUNT as usize + 1;
+        let source = "nop\n".repeat(count);
+        assert!(errors(&source).iter().any(|error| error.contains("Program reached maximum size")));
+
+        let config = AssemblerConfig {
+            banks: true,
+            ..AssemblerConfig::default()
+        };
+
+        let assembler = parsed(config, &source).unwrap();
+        assert_eq!(assembler.assemble().unwrap().len(), count);
+        assert!(assembler.assemble_to_writer(&mut Vec::new()).is_err());
+
+        assert_eq!(Assembler::bank_path("out.bin", 1), "out.1.bin");
+        assert_eq!(Assembler::bank_path("out", 0), "out.0");
+    }
+
+    fn output(output_format: OutputFormat, source: &str) -> String {
+        let config = AssemblerConfig {
+            output_format,
+            ..AssemblerConfig::default()
+        };
+
+        let assembler = parsed(config, source).unwrap();
+
+        let mut output = Vec::new();
+        assembler.assemble_to_writer(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn listing_header() {
+        let listing = output(OutputFormat::Listing, "#define VALUE 5\nstart:\nldi r1 VALUE\njmp start");
+
+        assert!(listing.starts_with("// Defines\n"));
+        assert!(listing.contains("// VALUE = 5\n"));
+        assert!(listing.contains("// Labels\n// start = 0x0000\n"));
+        assert!(listing.contains("0x0000  1000000100000101  // Line 3"));
+    }
+
+    #[test]
+    fn memory_disabled() {
+        let config = AssemblerConfig {
+            allow_memory: false,
+            ..AssemblerConfig::default()
+        };
+
+        assert_eq!(assemble_with(config.clone(), "lod r1 r2 0"), Err(vec!["[Line 1] Memory instructions are disabled, \"lod\" can't be used".to_string()]));
+        assert!(assemble_with(config.clone(), "str r1 r2 0").is_err());
+        assert!(assemble_with(config, "add r1 r2 r3").is_ok());
+    }
+
+    #[test]
+    fn data_directives() {
+        assert_eq!(assemble(".byte 1 -1 0xFF"), vec![1, 0x00FF, 0x00FF]);
+        assert_eq!(errors(".byte 256"), vec!["[Line 1] Value \"256\" doesn't fit in 8 bits (-128 to 255)".to_string()]);
+
+        assert_eq!(assemble(".2byte 0x1234\n.short -1\n.word 65535\n.data 2"), vec![0x1234, 0xFFFF, 0xFFFF, 2]);
+        assert_eq!(errors(".word 65536"), vec!["[Line 1] Value \"65536\" doesn't fit in 16 bits (-32768 to 65535)".to_string()]);
+    }
+
+    #[test]
+    fn duplicate_labels_at_same_address() {
+        assert_eq!(assemble("end:\nend:\njmp end"), vec![assemble("end:\njmp end")[0]]);
+        assert_eq!(errors("end:\nnop\nend:"), vec!["[Line 3] Label \"end\" was already defined".to_string()]);
+
+        let config = AssemblerConfig {
+            merge_duplicate_labels: false,
+            ..AssemblerConfig::default()
+        };
+
+        assert_eq!(assemble_with(config, "end:\nend:\njmp end"), Err(vec!["[Line 2] Label \"end\" was already defined".to_string()]));
+    }
+
+    #[test]
+    fn size_profile() {
+        let assembler = parsed(AssemblerConfig::default(), "helper:\nret\nmain:\nnop\nnop\nhlt\nalso_main:\nend:").unwrap();
+        assert_eq!(assembler.size_profile(), vec![
+            ("main".to_string(), 3),
+            ("helper".to_string(), 1),
+            ("also_main".to_string(), 0),
+            ("end".to_string(), 0)
+        ]);
+    }
+
+    #[test]
+    fn label_defines_round_trip() {
+        let directory = std::env::temp_dir().join("batpu_label_defines_round_trip");
+        fs::create_dir_all(&directory).unwrap();
+        let defines_path = directory.join("a.def");
+
+        let a = parsed(AssemblerConfig::default(), "nop\nentry:\nhlt").unwrap();
+        a.write_label_defines(defines_path.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read_to_string(&defines_path).unwrap(), "#define entry 1\n");
+
+        let mut b = Assembler::new(AssemblerConfig::default());
+        b.parse_with_overlay(directory.join("b.asm").to_str().unwrap(), "#include \"a.def\"\nldi r1 entry").unwrap();
+        assert_eq!(b.assemble().unwrap(), vec![0x8101]);
+    }
+
+    #[test]
+    fn from_instructions() {
+        let instructions = vec![
+            Instruction::LoadImmediate(Register::new(1).unwrap(), Immediate::new(5)),
+            Instruction::Jump(Location::Label("start".to_string())),
+            Instruction::Halt
+        ];
+
+        let labels = Labels::from([("start".to_string(), 0)]);
+        let assembler = Assembler::from_instructions(instructions.clone(), labels).unwrap();
+        assert_eq!(assembler.assemble().unwrap(), assemble("start:\nldi r1 5\njmp start\nhlt"));
+
+        let errors = Assembler::from_instructions(instructions, Labels::new()).err().unwrap();
+        assert_eq!(errors[0].description, "Label \"start\" used at address 1 was never defined");
+    }
+
+    fn warnings(config: AssemblerConfig, source: &str) -> Vec<String> {
+        parsed(config, source).unwrap().warnings().iter().map(|warning| warning.to_string()).collect()
+    }
+
+    #[test]
+    fn register_leading_zeros() {
+        let config = AssemblerConfig {
+            style_lints: true,
+            ..AssemblerConfig::default()
+        };
+
+        assert_eq!(warnings(config.clone(), "inc r05"), vec!["[Line 1] Register \"r05\" has leading zeros".to_string()]);
+        assert!(warnings(config, "inc r5").is_empty());
+        assert!(warnings(AssemblerConfig::default(), "inc r05").is_empty());
+    }
+
+    #[test]
+    fn report_sections() {
+        let report = output(OutputFormat::Report, "start:\nhlt");
+        let usage = format!("1 out of {} instructions used", Assembler::with_commas(address::MAX_POSSIBLE_COUNT));
+
+        assert!(report.starts_with("0001000000000000\n\n// Symbols\n// start = 0x0000\n\n// Stats\n"));
+        assert!(report.contains(&usage));
+    }
+
+    #[test]
+    fn character_literal_hint() {
+        assert_eq!(
+            assemble_with(strict(), "ldi r1 247\nldi r2 0x1C\nstr r1 r2 0\nhlt"),
+            Err(vec!["[Line 2] Immediate \"0x1C\" could be written as the character literal '!'".to_string()])
+        );
+
+        assert!(assemble_with(strict(), "ldi r1 247\nldi r2 '!'\nstr r1 r2 0\nhlt").is_ok());
+        assert!(assemble_with(strict(), "ldi r1 240\nldi r2 0x1C\nstr r1 r2 0\nhlt").is_ok());
+    }
+
+    #[test]
+    fn forced_label_reference() {
+        assert_eq!(assemble("nop\n5loop:\njmp @5loop"), assemble("nop\nloop:\njmp loop"));
+        assert_eq!(assemble("nop\n5loop:\njmp @5loop+1\nhlt"), assemble("nop\nloop:\njmp loop+1\nhlt"));
+        assert_eq!(errors("jmp @"), vec!["[Line 1:5] Label reference \"@\" is missing a name".to_string()]);
+    }
+
+    #[test]
+    fn literals_too_large() {
+        assert_eq!(errors("ldi r1 0xFFFFFFFFFF"), vec!["[Line 1:8] Literal \"0xFFFFFFFFFF\" is too large for immediate (maximum is 255)".to_string()]);
+        assert_eq!(errors("lod r1 r2 0xFFFFFFFFFF"), vec!["[Line 1:11] Literal \"0xFFFFFFFFFF\" is too large for offset (maximum is 7)".to_string()]);
+        assert_eq!(errors("jmp 0xFFFFFFFFFF"), vec![format!("[Line 1:5] Literal \"0xFFFFFFFFFF\" is too large for address (maximum is {})", address::MAX_VALUE)]);
+    }
+
+    #[test]
+    fn port_usage() {
+        let assembler = parsed(AssemblerConfig::default(), "ldi r1 SCR_CLR\nstr r1 r0 0\nhlt").unwrap();
+        let usage = assembler.port_usage();
+
+        assert!(usage.contains(&("SCR_CLR", "246", true)));
+        assert!(usage.contains(&("SCR_PUSH", "245", false)));
+    }
+
+    #[test]
+    fn define_cycles() {
+        let self_reference = errors("#define X X\nldi r1 X");
+        assert_eq!(self_reference.len(), 1);
+        assert!(self_reference[0].contains("Definition of \"X\" refers to itself"));
+
+        let cycle = errors("#define A B\n#define B A\nldi r1 A");
+        assert_eq!(cycle.len(), 1);
+        assert!(cycle[0].contains("Definitions form a cycle: A -> B -> A"));
+    }
+
+    struct NoLoadIntoR14;
+
+    impl InstructionValidator for NoLoadIntoR14 {
+        fn validate(&self, address: u32, instruction: &Instruction) -> Result<(), String> {
+            let Instruction::LoadImmediate(..) = instruction else {
+                return Ok(());
+            };
+
+            match instruction.binary(address, &Labels::new()) {
+                Ok(word) if encoding::register_a(word) == 14 => Err(format!("r14 is reserved, \"ldi\" at address {} can't load into it", address)),
+                _ => Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn custom_validator() {
+        let mut assembler = Assembler::new(AssemblerConfig::default());
+        assembler.add_validator(Box::new(NoLoadIntoR14));
+
+        let errors: Vec<String> = assembler.parse("ldi r1 5\nldi r14 5\nhlt").unwrap_err().iter().map(|error| error.to_string()).collect();
+        assert_eq!(errors, vec!["[Line 2] r14 is reserved, \"ldi\" at address 1 can't load into it".to_string()]);
+    }
+
+    #[test]
+    fn offset_define_out_of_range() {
+        assert_eq!(errors("#define FIELD 9\nlod r1 r2 FIELD"), vec!["[Line 2:11] Offset 9 (from \"FIELD\") is out of range, it must be between -8 and 7".to_string()]);
+        assert_eq!(assemble("#define FIELD 3\nlod r1 r2 FIELD"), assemble("lod r1 r2 3"));
+    }
+
+    #[test]
+    fn sexpr_output() {
+        assert_eq!(output(OutputFormat::SExpr, "add r1 r2 r3\n.word 5"), "(add (reg 1) (reg 2) (reg 3))\n(word 0x0005)");
+    }
+
+    #[test]
+    fn default_word_width() {
+        assert_eq!(BITS, 16);
+        assert_eq!(size_of::<Word>(), 2);
+
+        let assembler = parsed(AssemblerConfig::default(), "ldi r1 5\nhlt").unwrap();
+        assert_eq!(assembler.assemble_bytes(true).unwrap(), vec![0x81, 0x05, 0x10, 0x00]);
+        assert_eq!(output(OutputFormat::Text, "ldi r1 5"), "1000000100000101");
+    }
+
+    #[test]
+    fn output_path_is_directory() {
+        let directory = std::env::temp_dir();
+        let directory = directory.to_str().unwrap();
+
+        let mut assembler = parsed(AssemblerConfig::default(), "hlt").unwrap();
+        let errors: Vec<String> = assembler.assemble_to_file(directory).unwrap_err().iter().map(|error| error.to_string()).collect();
+        assert_eq!(errors, vec![format!("Output path \"{}\" is a directory", directory)]);
+    }
+
+    #[test]
+    fn jump_to_next_instruction() {
+        assert_eq!(assemble_with(strict(), "jmp next\nnext:\nhlt"), Err(vec!["[Line 1] \"jmp\" to the next instruction does nothing".to_string()]));
+        assert_eq!(assemble_with(strict(), "brh ne +1\nhlt"), Err(vec!["[Line 1] \"brh\" to the next instruction does nothing".to_string()]));
+
+        let config = AssemblerConfig {
+            warnings: true,
+            ..AssemblerConfig::default()
+        };
+
+        assert!(!warnings(config, "jmp done\nnop\ndone:\nhlt").iter().any(|warning| warning.contains("does nothing")));
+    }
+
+    #[test]
+    fn build_version() {
+        let config = AssemblerConfig {
+            build_version: Some(7),
+            ..AssemblerConfig::default()
+        };
+
+        assert_eq!(assemble_with(config, "ldi r1 __VERSION__"), Ok(vec![0x8107]));
+        assert!(!errors("ldi r1 __VERSION__").is_empty());
+    }
+
+    #[test]
+    fn identical_source_operands() {
+        assert_eq!(assemble_with(strict(), "sub r1 r1 r2\nhlt"), Err(vec!["[Line 1] Subtracting \"r1\" from itself always results in 0".to_string()]));
+        assert!(assemble_with(strict(), "xor r1 r1 r1\nhlt").is_ok());
+        assert!(assemble_with(strict(), "sub r1 r2 r3\nhlt").is_ok());
+    }
+
+    #[test]
+    fn label_name_characters() {
+        assert_eq!(errors("foo+bar:"), vec!["[Line 1] Label \"foo+bar\" contains '+', only letters, digits, '_' and '.' are allowed".to_string()]);
+        assert_eq!(assemble("foo_bar:\njmp foo_bar"), assemble("loop:\njmp loop"));
+    }
+
+    #[test]
+    fn hex_output() {
+        assert_eq!(output(OutputFormat::Hex, "ldi r1 0xAB\nhlt"), "81AB\n1000");
+    }
+
+    #[test]
+    fn logisim_output() {
+        assert_eq!(output(OutputFormat::Logisim, "ldi r1 0xAB\nnop\nnop\nnop\nhlt"), "v2.0 raw\n81ab\n3*0\n1000");
+    }
+
+    #[test]
+    fn file_matches_writer() {
+        let path = std::env::temp_dir().join("batpu_file_matches_writer.bin");
+        let mut assembler = parsed(AssemblerConfig::default(), "ldi r1 5\nhlt").unwrap();
+
+        let mut output = Vec::new();
+        assembler.assemble_to_writer(&mut output).unwrap();
+        assembler.assemble_to_file(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(output, vec![0x81, 0x05, 0x10, 0x00]);
+        assert_eq!(fs::read(&path).unwrap(), output);
+    }
+
+    #[test]
+    fn words_per_line() {
+        let config = AssemblerConfig {
+            output_format: OutputFormat::Hex,
+            words_per_line: 2,
+            ..AssemblerConfig::default()
+        };
+
+        let assembler = parsed(config, "nop\nnop\nnop\nhlt\nhlt").unwrap();
+
+        let mut output = Vec::new();
+        assembler.assemble_to_writer(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "0000 0000\n0000 1000\n1000");
+        assert_eq!(assembler.output_byte_len(), "0000 0000\n0000 1000\n1000".len());
+    }
+
+    #[test]
+    fn listing_file() {
+        let directory = std::env::temp_dir();
+        let listing_path = directory.join("batpu_listing_file.lst");
+
+        let config = AssemblerConfig {
+            listing_output: Some(listing_path.to_string_lossy().into_owned()),
+            ..AssemblerConfig::default()
+        };
+
+        let mut assembler = parsed(config, "// comment\nldi r1 5\n.word 1 2\nhlt").unwrap();
+        assembler.assemble_to_file(directory.join("batpu_listing_file.bin").to_str().unwrap()).unwrap();
+
+        assert_eq!(fs::read_to_string(&listing_path).unwrap(), [
+            "              // comment",
+            "0x0000  8105  ldi r1 5",
+            "0x0001  0001  .word 1 2",
+            "0x0002  0002",
+            "0x0003  1000  hlt"
+        ].join("\n"));
+    }
+
+    #[test]
+    fn longest_basic_block() {
+        let assembler = parsed(AssemblerConfig::default(), "ldi r1 1\nloop:\nadd r1 r1 r1\nadd r1 r1 r1\nadd r1 r1 r1\nbrh ne loop\nhlt").unwrap();
+        assert_eq!(assembler.longest_basic_block(), Some((1, 4)));
+
+        let data = parsed(AssemblerConfig::default(), "hlt\n.word 1 2 3").unwrap();
+        assert_eq!(data.longest_basic_block(), Some((0, 1)));
+
+        assert_eq!(parsed(AssemblerConfig::default(), "").unwrap().longest_basic_block(), None);
+    }
+
+    #[test]
+    fn symbols_file() {
+        let path = std::env::temp_dir().join("batpu_symbols_file.sym");
+
+        let assembler = parsed(AssemblerConfig::default(), "start:\nnop\nloop:\njmp loop\nalso_loop:").unwrap();
+        assembler.write_symbols(path.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "start = 0x0000\nloop = 0x0001\nalso_loop = 0x0002\n");
+    }
+
+    fn write_files(directory: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(directory);
+
+        for (name, contents) in files {
+            let path = directory.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        directory
+    }
+
+    fn parsed_file(path: &Path) -> Result<Assembler, Vec<String>> {
+        let mut assembler = Assembler::new(AssemblerConfig::default());
+        match assembler.parse_file(path.to_str().unwrap()) {
+            Ok(()) => Ok(assembler),
+            Err(errors) => Err(errors.iter().map(|error| error.to_string()).collect())
+        }
+    }
+
+    #[test]
+    fn includes() {
+        let directory = write_files("batpu_includes", &[
+            ("main.asm", "#include \"lib/lib.asm\"\nstart:\nldi r1 VALUE\ncal helper"),
+            ("lib/lib.asm", "#define VALUE 3\nhelper:\nret"),
+            ("bad.asm", "nop\n#include \"lib/bad.asm\""),
+            ("lib/bad.asm", "nop\nfoo")
+        ]);
+
+        let main = parsed_file(&directory.join("main.asm")).unwrap();
+        assert_eq!(main.assemble().unwrap(), assemble("helper:\nret\nstart:\nldi r1 3\ncal helper"));
+
+        let errors = parsed_file(&directory.join("bad.asm")).err().unwrap();
+        assert_eq!(errors, vec!["[Line 2 in \"lib/bad.asm\"] Unknown opcode: foo".to_string()]);
+    }
+
+    #[test]
+    fn include_cycle() {
+        let directory = write_files("batpu_include_cycle", &[
+            ("a.asm", "#include \"b.asm\""),
+            ("b.asm", "#include \"a.asm\"")
+        ]);
+
+        let errors = parsed_file(&directory.join("a.asm")).err().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("[Line 1 in \"b.asm\"] Include cycle: "));
+        assert!(errors[0].ends_with("a.asm\""));
+    }
+
+    #[test]
+    fn embedded_source() {
+        let config = AssemblerConfig {
+            output_format: OutputFormat::Hex,
+            embed_source: true,
+            ..AssemblerConfig::default()
+        };
+
+        let assembler = parsed(config.clone(), "ldi r1 5\n\nhlt").unwrap();
+
+        let mut output = Vec::new();
+        assembler.assemble_to_writer(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "8105\n1000\n// ldi r1 5\n//\n// hlt");
+
+        let binary = parsed(AssemblerConfig { output_format: OutputFormat::Binary, ..config }, "hlt").unwrap();
+        assert!(binary.assemble_to_writer(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn define_used_signed_and_unsigned() {
+        assert_eq!(
+            assemble_with(strict(), "#define STEP 200\nldi r1 STEP\nadi r1 STEP\nhlt"),
+            Err(vec!["[Line 3] Define \"STEP\" is used by both ldi and adi, which read it as 200 and -56".to_string()])
+        );
+
+        assert!(assemble_with(strict(), "#define STEP 100\nldi r1 STEP\nadi r1 STEP\nhlt").is_ok());
+        assert!(assemble_with(strict(), "#define STEP 200\nldi r1 STEP\nldi r2 STEP\nhlt").is_ok());
+    }
+
+    #[test]
+    fn org() {
+        assert_eq!(assemble("jmp start\n.org 3\nstart:\nhlt"), assemble("jmp start\nnop\nnop\nstart:\nhlt"));
+        assert_eq!(assemble("hlt\n.org 3 0xFFFF\nhlt"), vec![0x1000, 0xFFFF, 0xFFFF, 0x1000]);
+        assert_eq!(errors("nop\nnop\n.org 1"), vec!["[Line 3] Can't move back to address 1, already at address 2".to_string()]);
+
+        let past_end = (address::MAX_VALUE + 1).to_string();
+        assert_eq!(errors(&format!(".org {}", past_end)), vec![format!("[Line 1] Address {} is past the end of the program (maximum is {})", past_end, address::MAX_VALUE)]);
+    }
+
+    #[test]
+    fn undef() {
+        assert_eq!(errors("#undef VALUE"), vec!["[Line 1] Definition of \"VALUE\" doesn't exist".to_string()]);
+        assert!(!errors("#define VALUE 1\n#undef VALUE\nldi r1 VALUE").is_empty());
+        assert_eq!(assemble("#define VALUE 1\n#undef VALUE\n#define VALUE 2\nldi r1 VALUE"), vec![0x8102]);
+        assert_eq!(assemble("#undef RNG\n#define RNG 3\nldi r1 RNG"), vec![0x8103]);
+    }
+
+    #[test]
+    fn include_binary() {
+        let directory = write_files("batpu_include_binary", &[("main.asm", ".include_bin \"blob.bin\"\nhlt"), ("odd.asm", ".include_bin \"odd.bin\"")]);
+        fs::write(directory.join("blob.bin"), b"\x12\x34\xAB\xCD").unwrap();
+        fs::write(directory.join("odd.bin"), b"\x12\x34\xAB").unwrap();
+
+        let main = parsed_file(&directory.join("main.asm")).unwrap();
+        assert_eq!(main.assemble().unwrap(), vec![0x1234, 0xABCD, 0x1000]);
+
+        let mut little_endian = Assembler::new(AssemblerConfig { little_endian: true, ..AssemblerConfig::default() });
+        little_endian.parse_file(directory.join("main.asm").to_str().unwrap()).unwrap();
+        assert_eq!(little_endian.assemble_bytes(false).unwrap(), vec![0x12, 0x34, 0xAB, 0xCD, 0x00, 0x10]);
+
+        assert_eq!(parsed_file(&directory.join("odd.asm")).err().unwrap(), vec!["[Line 1] \"odd.bin\" is 3 bytes long, which isn't a multiple of 2".to_string()]);
+    }
+
+    #[test]
+    fn data_words_take_up_addresses() {
+        assert_eq!(assemble("jmp end\ntable:\n.data 7 8\nend:\nldi r1 table+1\nhlt"), vec![0xA003, 7, 8, 0x8102, 0x1000]);
+
+        let words = format!(".word {}", vec!["0"; address::MAX_POSSIBLE_COUNT as usize + 1].join(" "));
+        assert!(errors(&words).iter().any(|error| error.contains("Program reached maximum size")));
+    }
+
+    #[test]
+    fn verify_encoding() {
+        let config = AssemblerConfig {
+            verify_encoding: true,
+            ..AssemblerConfig::default()
+        };
+
+        assert_eq!(assemble_with(config, "nop\nhlt\nret\nadd r1 r2 r3"), Ok(vec![0x0000, 0x1000, 0xD000, 0x2123]));
+
+        let error = Assembler::verify_encoding(&Instruction::Halt, 0x1001, 3, 2).unwrap_err();
+        assert_eq!(error.to_string(), "[Line 2] \"hlt\" at address 3 was encoded as 0x1001, which isn't a valid \"hlt\"");
+        assert!(Assembler::verify_encoding(&Instruction::Return, 0xD000, 0, 1).is_ok());
+    }
+
+    #[test]
+    fn string_directive() {
+        assert_eq!(assemble(".string \"HI THERE!\""), vec![8, 9, 0, 20, 8, 5, 18, 5, 28]);
+        assert_eq!(errors(".string \"hi\""), vec!["[Line 1] Character \"h\" is not supported, you can only use ones in \" ABCDEFGHIJKLMNOPQRSTUVWXYZ.!?\"".to_string()]);
+        assert_eq!(errors(".string HI"), vec!["[Line 1] Text HI must be in quotes".to_string()]);
+    }
+
+    #[test]
+    fn trailing_comment_in_listing() {
+        let listing = output(OutputFormat::Listing, "// heading\nldi r1 5 // five\nhlt");
+
+        assert!(listing.ends_with("0x0000  1000000100000101  // Line 2: five\n0x0001  0001000000000000  // Line 3"));
+        assert!(!listing.contains("heading"));
+    }
+
+    #[test]
+    fn max_line_length() {
+        let config = AssemblerConfig {
+            style_lints: true,
+            max_line_length: 10,
+            ..AssemblerConfig::default()
+        };
+
+        assert_eq!(warnings(config.clone(), "hlt\nldi r1 5 // five"), vec!["[Line 2] Line is 16 characters long, the maximum is 10".to_string()]);
+        assert!(warnings(config, "ldi r1 5\nhlt").is_empty());
+        assert!(warnings(AssemblerConfig::default(), "ldi r1 5 // five").is_empty());
+    }
+
+    #[test]
+    fn region() {
+        let assembler = parsed(AssemblerConfig::default(), "main:\ncal routine\nhlt\nroutine:\nldi r1 1\njmp routine\ndone:\nhlt").unwrap();
+
+        let routine = assembler.region("routine").unwrap();
+        assert_eq!(routine.assemble().unwrap(), vec![0x8101, 0xA000]);
+        assert_eq!(routine.labels().get("routine"), Some(&0));
+
+        let errors: Vec<String> = assembler.region("main").unwrap_err().iter().map(|error| error.to_string()).collect();
+        assert_eq!(errors, vec!["[Line 2] Label \"routine\" is outside of \"main\"".to_string()]);
+
+        let errors: Vec<String> = assembler.region("missing").unwrap_err().iter().map(|error| error.to_string()).collect();
+        assert_eq!(errors, vec!["Label \"missing\" was never defined".to_string()]);
+    }
+
+    #[test]
+    fn register_aliases() {
+        assert_eq!(assemble("#define counter r7\ninc counter"), assemble("inc r7"));
+        assert_eq!(assemble("#define counter r7\n#define ctr counter\ninc ctr"), assemble("inc r7"));
+
+        assert_eq!(errors("#define bad x5\ninc bad"), vec!["[Line 2:5] Register \"x5\" must start with a lowercase 'r' (from \"bad\")".to_string()]);
+
+        let errors = errors("#define counter r16\n#define ctr counter\ninc ctr");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("[Line 3:5] "));
+        assert!(errors[0].ends_with(" (from \"ctr\")"));
+    }
+
+    #[test]
+    fn condition_aliases() {
+        for (i, (&condition, &alias)) in encoding::CONDITIONS.iter().zip(&encoding::CONDITION_ALIASES).enumerate() {
+            let word = 0xB000 | (i as Word) << 10;
+            assert_eq!(assemble(&format!("loop:\nbrh {} loop", condition)), vec![word]);
+            assert_eq!(assemble(&format!("loop:\nbrh {} loop", alias)), vec![word]);
+        }
+
+        assert_eq!(errors("loop:\nbrh gt loop"), vec!["[Line 2:5] Unknown condition: \"gt\"".to_string()]);
+    }
+
+    #[test]
+    fn character_literal_hint_only_for_display() {
+        let config = AssemblerConfig {
+            warnings: true,
+            ..AssemblerConfig::default()
+        };
+
+        let hints = |source: &str| -> Vec<String> {
+            warnings(config.clone(), source)
+                .into_iter()
+                .filter(|warning| warning.contains("character literal"))
+                .collect()
+        };
+
+        assert_eq!(hints("ldi r1 247\nldi r2 5\nstr r1 r2 0\nhlt"), vec!["[Line 2] Immediate \"5\" could be written as the character literal 'E'".to_string()]);
+        assert!(hints("ldi r2 5\nhlt").is_empty());
+        assert!(hints("ldi r1 247\nldi r2 5\nnext:\nstr r1 r2 0\nhlt").is_empty());
+        assert_eq!(hints("ldi r1 248\nldi r2 5\nstr r1 r2 -1\nhlt").len(), 1);
+    }
+
+    #[test]
+    fn interleave_nops() {
+        let config = AssemblerConfig {
+            interleave_nops: true,
+            ..AssemblerConfig::default()
+        };
+
+        let source = "start:\nldi r1 1\nloop:\ndec r1\nbrh ne loop\njmp start\nend:\n.word 7\nhlt";
+        let assembler = parsed(config.clone(), source).unwrap();
+
+        assert_eq!(assembler.labels().get("loop"), Some(&2));
+        assert_eq!(assembler.labels().get("end"), Some(&8));
+        assert_eq!(
+            assemble_with(config, source),
+            Ok(vec![0x8101, 0x0000, 0x91FF, 0x0000, 0xB402, 0x0000, 0xA000, 0x0000, 7, 0x1000, 0x0000])
+        );
+    }
+
+    #[test]
+    fn unicode_whitespace() {
+        assert_eq!(assemble("ldi\u{a0}r1\u{a0}5"), vec![0x8105]);
+        assert_eq!(assemble("add\u{2003}r1 r2\u{3000}r3"), vec![0x2123]);
+        assert_eq!(errors("ldi r1\u{a0}x"), errors("ldi r1 x").iter().map(|error| error.replacen(":8]", "]", 1)).collect::<Vec<String>>());
+    }
+
+    #[test]
+    fn errors_sorted_and_deduplicated() {
+        assert_eq!(
+            errors(".pin 16\nhlt;;;"),
+            vec!["[Line 1] Pinned region is missing \".endpin\"".to_string(), "[Line 2] Useless semicolon".to_string()]
+        );
+    }
+
+    #[test]
+    fn signed_listing() {
+        let config = AssemblerConfig {
+            output_format: OutputFormat::Listing,
+            signed_listing: true,
+            ..AssemblerConfig::default()
+        };
+
+        let assembler = parsed(config, ".word 0x8000\n.word 0xFFFF 5").unwrap();
+        let mut listing = Vec::new();
+        assembler.assemble_to_writer(&mut listing).unwrap();
+
+        assert!(String::from_utf8(listing).unwrap().ends_with(
+            "0x0000  1000000000000000  -32768  // Line 1\n0x0001  1111111111111111      -1  // Line 2\n0x0002  0000000000000101       5  // Line 2"
+        ));
+        assert!(output(OutputFormat::Listing, ".word 0xFFFF").ends_with("0x0000  1111111111111111  // Line 1"));
+    }
+
+    #[test]
+    fn immediate_range() {
+        assert_eq!(assemble("ldi r1 -128\nldi r1 255"), vec![0x8180, 0x81FF]);
+        assert_eq!(errors("ldi r1 256"), vec!["[Line 1:8] Immediate 256 is out of range, it must be between -128 and 255".to_string()]);
+        assert_eq!(errors("adi r1 -129"), vec!["[Line 1:8] Immediate -129 is out of range, it must be between -128 and 255".to_string()]);
+        assert_eq!(errors("ldi r1 0x100"), vec!["[Line 1:8] Immediate 256 (from \"0x100\") is out of range, it must be between -128 and 255".to_string()]);
+    }
+
+    #[test]
+    fn isa_revision_defines() {
+        let revision = |isa_revision| AssemblerConfig {
+            isa_revision,
+            ..AssemblerConfig::default()
+        };
+
+        assert_eq!(assemble_with(revision(IsaRevision::V1), "ldi r1 CHAR_DISP_PUSH\nldi r2 SCR_PUSH"), Ok(vec![0x81F7, 0x82F5]));
+        assert_eq!(assemble_with(revision(IsaRevision::V2), "ldi r1 CHAR_DISP_PUSH\nldi r2 SCR_DRAW\nldi r3 CHAR_DISP_WRITE"), Ok(vec![0x81F8, 0x82F5, 0x83F7]));
+
+        assert!(assemble_with(revision(IsaRevision::V1), "ldi r1 CHAR_DISP_WRITE").is_err());
+        assert!(assemble_with(revision(IsaRevision::V2), "ldi r1 SCR_PUSH").is_err());
+    }
+
+    #[test]
+    fn jump_targets_past_address_field() {
+        assert_eq!(errors("jmp far\n.org 2000\nfar:\nhlt"), vec!["[Line 1] Label \"far\" is at address 2000, but jumps can only reach addresses up to 1023".to_string()]);
+        assert_eq!(errors("jmp 1024"), vec!["[Line 1:5] Address 1024 is past the last address jumps can reach (1023)".to_string()]);
+        assert_eq!(assemble("jmp 1023"), vec![0xA3FF]);
+    }
+
+    #[test]
+    fn reserved_registers() {
+        assert_eq!(errors(".reserve r14 r15\nldi r13 1\nldi r14 1"), vec!["[Line 3:5] Register \"r14\" is reserved".to_string()]);
+        assert_eq!(errors(".reserve r16"), vec!["[Line 1] Register \"r16\" is invalid".to_string()]);
+
+        let source = ".reserve r15\n.macro push\nldi r15 1\n.endmacro\npush";
+        assert_eq!(assemble(source), vec![0x8F01]);
+
+        let config = AssemblerConfig {
+            allow_reserved_in_macros: false,
+            ..AssemblerConfig::default()
+        };
+
+        assert_eq!(assemble_with(config, source).unwrap_err(), vec!["[Line 5] In macro \"push\":\n[Line 5] Register \"r15\" is reserved".to_string()]);
+    }
+
+    #[test]
+    fn parse_with_overlay() {
+        let directory = write_files("batpu_overlay", &[
+            ("main.asm", "hlt"),
+            ("values.asm", "#define VALUE 7")
+        ]);
+
+        let overlay = "#include \"values.asm\"\nldi r1 VALUE";
+
+        let mut assembler = Assembler::new(AssemblerConfig::default());
+        assert!(assembler.parse_with_overlay(directory.join("main.asm").to_str().unwrap(), overlay).is_ok());
+        assert_eq!(assembler.assemble().unwrap(), vec![0x8107]);
+
+        // Unsaved files don't exist yet
+        let mut assembler = Assembler::new(AssemblerConfig::default());
+        assert!(assembler.parse_with_overlay(directory.join("unsaved.asm").to_str().unwrap(), overlay).is_ok());
+        assert_eq!(assembler.assemble().unwrap(), vec![0x8107]);
+
+        assert_eq!(parsed_file(&directory.join("main.asm")).unwrap().assemble().unwrap(), vec![0x1000]);
+    }
+
+    #[test]
+    fn label_offsets() {
+        assert_eq!(assemble("nop\nloop:\njmp loop+3\nbrh ne loop-1\ncal loop+2"), vec![0x0000, 0xA004, 0xB400, 0xC003]);
+        assert_eq!(errors("loop:\njmp loop-1"), vec!["[Line 2] \"loop-1\" is address -1, which is outside of 0 to 1023".to_string()]);
+        assert_eq!(errors("jmp nowhere+1"), vec!["[Line 1] Label \"nowhere\" was never defined".to_string()]);
+    }
+
+    #[test]
+    fn unterminated_literals() {
+        let unterminated = |position: &str| vec![format!("[Line {}] Unterminated character/string literal", position)];
+
+        assert_eq!(errors("hlt; ldi r1 'A"), unterminated("1:13"));
+        assert_eq!(errors(".string \"AB"), unterminated("1:9"));
+        assert_eq!(errors("ldi r1 '\\'"), unterminated("1:8"));
+        assert_eq!(assemble("ldi r1 'A'; hlt"), vec![0x8101, 0x1000]);
+    }
+
+    #[test]
+    fn labels_and_defines() {
+        let assembler = parsed(AssemblerConfig::default(), "#define COUNT 3\nstart:\nnop\nend:").unwrap();
+
+        assert_eq!(assembler.labels().len(), 2);
+        assert_eq!(assembler.labels().get("start"), Some(&0));
+        assert_eq!(assembler.labels().get("end"), Some(&1));
+
+        assert_eq!(assembler.defines().get("COUNT").map(String::as_str), Some("3"));
+        assert_eq!(assembler.defines().get("RNG").map(String::as_str), Some("254"));
+    }
+
+    #[test]
+    fn source_text_after_edit() {
+        let old = parsed(AssemblerConfig::default(), "ldi r1 1\nloop:\njmp loop").unwrap();
+        let new = parsed(AssemblerConfig::default(), "ldi r1 1\ninc r1\nloop:\njmp loop").unwrap();
+
+        let (old_code, new_code) = (old.assemble().unwrap(), new.assemble().unwrap());
+        let changed: Vec<usize> = (0..new_code.len()).filter(|&address| old_code.get(address) != new_code.get(address)).collect();
+        assert_eq!(changed, vec![1, 2]);
+
+        assert_eq!(old.source_text(1), Some((None, 3, "jmp loop")));
+        assert_eq!(new.source_text(1), Some((None, 2, "inc r1")));
+        assert_eq!(new.source_text(2), Some((None, 4, "jmp loop")));
+        assert_eq!(old.source_text(2), None);
+    }
+
+    #[test]
+    fn assemble_bytes() {
+        let assembler = parsed(AssemblerConfig::default(), "ldi r1 5\nhlt").unwrap();
+
+        assert_eq!(assembler.assemble_bytes(true).unwrap(), vec![0x81, 0x05, 0x10, 0x00]);
+        assert_eq!(assembler.assemble_bytes(false).unwrap(), vec![0x05, 0x81, 0x00, 0x10]);
+        assert_eq!(output(OutputFormat::Binary, "ldi r1 5\nhlt").into_bytes(), assembler.assemble_bytes(true).unwrap());
+    }
+
+    #[test]
+    fn little_endian_output() {
+        let written = |output_format, little_endian| {
+            let config = AssemblerConfig {
+                output_format,
+                little_endian,
+                ..AssemblerConfig::default()
+            };
+
+            let mut output = Vec::new();
+            parsed(config, "ldi r1 5").unwrap().assemble_to_writer(&mut output).unwrap();
+            output
+        };
+
+        assert_eq!(written(OutputFormat::Binary, false), vec![0x81, 0x05]);
+        assert_eq!(written(OutputFormat::Binary, true), vec![0x05, 0x81]);
+        assert_eq!(written(OutputFormat::Text, true), written(OutputFormat::Text, false));
+    }
+
+    #[test]
+    fn pseudo_instructions_with_r0() {
+        let config = AssemblerConfig {
+            warnings: true,
+            ..AssemblerConfig::default()
+        };
+
+        let warned = |source: &str, warning: &str| warnings(config.clone(), source).contains(&format!("[Line 1] {}", warning));
+
+        assert!(warned("mov r0 r1\nhlt", "\"mov\" of r0 always results in 0"));
+        assert!(warned("neg r0 r1\nhlt", "\"neg\" of r0 always results in 0"));
+        assert!(warned("not r0 r1\nhlt", "\"not\" of r0 always results in 255"));
+        assert!(warned("mov r1 r0\nhlt", "\"mov\" into r0 does nothing, since writes to r0 are discarded"));
+        assert!(!warned("mov r1 r2\nhlt", "\"mov\" of r0 always results in 0"));
+        assert!(warnings(AssemblerConfig::default(), "mov r0 r1\nhlt").is_empty());
+    }
+
+    #[test]
+    fn forward_labels_in_expressions() {
+        assert_eq!(assemble("ldi r1 (end-start)\nstart:\nnop\nnop\nend:\njmp end"), vec![0x8102, 0x0000, 0x0000, 0xA003]);
+
+        // end-start is 0 in the first pass, which mustn't drop the ldi and move the labels after it
+        assert_eq!(assemble("ldi r1 (256/(end-start))\nstart:\nnop\nnop\nend:\njmp end"), vec![0x8180, 0x0000, 0x0000, 0xA003]);
+    }
+
+    #[test]
+    fn defines_in_macros() {
+        let source = ".macro load register value\n#define TEMP value\nldi register TEMP\n.endmacro\nload r1 1\nload r2 2";
+
+        assert_eq!(assemble(source), vec![0x8101, 0x8202]);
+        assert!(!errors(&format!("{}\nldi r3 TEMP", source)).is_empty());
+        assert_eq!(errors("#define A 1\n#define A 2"), vec!["[Line 2] Definition of \"A\" already exists".to_string()]);
+    }
+
+    #[test]
+    fn octal_literals() {
+        assert_eq!(assemble("ldi r1 0o17\nlod r1 r2 0o7\njmp 0o12"), vec![0x810F, 0xE127, 0xA00A]);
+        assert_eq!(Assembler::parse_u32("0o7_77").unwrap(), 511);
+        assert_eq!(Assembler::parse_i32("0o10").unwrap(), 8);
+        assert!(Assembler::parse_i32("0o8").is_err());
+    }
+
+    #[test]
+    fn extra_arguments() {
+        assert_eq!(errors("add r1 r2 r3 extra"), vec!["[Line 1] Expected RegA, RegB and RegC (3 arguments), got 4 instead".to_string()]);
+
+        let config = AssemblerConfig {
+            warnings: true,
+            ignore_extra_arguments: true,
+            ..AssemblerConfig::default()
+        };
+
+        assert_eq!(assemble_with(config.clone(), "add r1 r2 r3 extra"), Ok(vec![0x2123]));
+        assert!(warnings(config.clone(), "add r1 r2 r3 extra more\nhlt").contains(&"[Line 1] Ignoring 2 extra arguments".to_string()));
+        assert!(assemble_with(config, "add r1 r2").is_err());
+    }
+
+    #[test]
+    fn escape_sequences() {
+        let assembler = Assembler::new(AssemblerConfig::default());
+        assert_eq!(assembler.get_literal_character("\\\\"), Ok('\\'));
+        assert_eq!(assembler.get_literal_character("\\'"), Ok('\''));
+        assert_eq!(assembler.get_literal_character("\\\""), Ok('"'));
+
+        assert_eq!(errors("ldi r1 '\\n'"), vec!["[Line 1:8] Escape sequence \"\\n\" has no character on the character display".to_string()]);
+        assert_eq!(errors("ldi r1 '\\q'"), vec!["[Line 1:8] Unknown escape sequence \"\\q\"".to_string()]);
+        assert!(errors("ldi r1 '\\''")[0].contains("Character \"'\" is not supported"));
+    }
+
+    #[test]
+    fn data_byte_order() {
+        let bytes = |little_endian| {
+            let config = AssemblerConfig {
+                little_endian,
+                ..AssemblerConfig::default()
+            };
+
+            let mut output = Vec::new();
+            parsed(config, ".word 0x1234").unwrap().assemble_to_writer(&mut output).unwrap();
+            output
+        };
+
+        assert_eq!(bytes(false), vec![0x12, 0x34]);
+        assert_eq!(bytes(true), vec![0x34, 0x12]);
+    }
+
+    #[test]
+    fn space_literal() {
+        assert_eq!(assemble("ldi r1 ' '"), vec![0x8100]);
+        assert_eq!(assemble("ldi r1 ' ' // space\nldi r2 'A'"), vec![0x8100, 0x8201]);
+        assert_eq!(Assembler::split_words("ldi  r1 ' '"), vec!["ldi", "r1", "' '"]);
+    }
+
+    #[test]
+    fn stores_inside_program() {
+        let config = AssemblerConfig {
+            warnings: true,
+            ..AssemblerConfig::default()
+        };
+
+        let overwrites = |config: AssemblerConfig, source: &str| -> Vec<String> {
+            warnings(config, source)
+                .into_iter()
+                .filter(|warning| warning.contains("overwrite code"))
+                .collect()
+        };
+
+        assert_eq!(
+            overwrites(config.clone(), "ldi r1 1\nstr r1 r2 0\nhlt"),
+            vec!["[Line 2] \"str\" to address 1 is inside the program (addresses 0 to 2), which would overwrite code if memory were shared".to_string()]
+        );
+        assert!(overwrites(config.clone(), "str r0 r2 5\nhlt").is_empty());
+        assert!(overwrites(config, "str r3 r2 0\nhlt").is_empty());
+        assert!(overwrites(AssemblerConfig::default(), "ldi r1 1\nstr r1 r2 0\nhlt").is_empty());
+    }
+
+    #[test]
+    fn semicolons_in_literals() {
+        assert_eq!(Assembler::split_unquoted("ldi r1 ';'; .string \"A;B\"", |c| c == ';'), vec!["ldi r1 ';'", " .string \"A;B\""]);
+        assert_eq!(assemble("ldi r1 'A'; ldi r2 ' '; hlt"), vec![0x8101, 0x8200, 0x1000]);
+        assert!(errors("ldi r1 ';'")[0].contains("Character \";\" is not supported"));
+    }
+
+    #[test]
+    fn playground_json() {
+        assert_eq!(
+            output(OutputFormat::PlaygroundJson, "start:\nldi r1 5\n\nhlt"),
+            "{\"words\":[33029,4096],\"symbols\":{\"start\":0},\"source_map\":[2,4]}"
+        );
+
+        let config = AssemblerConfig {
+            output_format: OutputFormat::PlaygroundJson,
+            pad_to: Some(2),
+            ..AssemblerConfig::default()
+        };
+
+        let mut json = Vec::new();
+        parsed(config, "hlt").unwrap().assemble_to_writer(&mut json).unwrap();
+        assert_eq!(String::from_utf8(json).unwrap(), "{\"words\":[4096,0],\"symbols\":{},\"source_map\":[1,null]}");
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let source = "start:\nldi r1 5 // five\nadd r1 r2 r3\nldi r2 \\\n6\njmp start\n";
+        let crlf = source.replace('\n', "\r\n");
+
+        assert_eq!(assemble(&crlf), assemble(source));
+        assert_eq!(output(OutputFormat::Listing, &crlf), output(OutputFormat::Listing, source));
+    }
+
+    #[test]
+    fn named_branches() {
+        assert_eq!(
+            assemble("loop:\njz loop\njnz loop\njc loop+1\njnc 5"),
+            assemble("loop:\nbrh zero loop\nbrh notzero loop\nbrh carry loop+1\nbrh notcarry 5")
+        );
+        assert_eq!(assemble("loop:\njz loop\njnz loop\njc loop\njnc loop"), vec![0xB000, 0xB400, 0xB800, 0xBC00]);
+        assert_eq!(errors("jz"), vec!["[Line 1] Expected Label/Address (1 argument), got 0 instead".to_string()]);
+    }
+
+    #[test]
+    fn check_only() {
+        let config = AssemblerConfig {
+            check_only: true,
+            ..AssemblerConfig::default()
+        };
+
+        let path = std::env::temp_dir().join("batpu_check_only.bin");
+        let _ = fs::remove_file(&path);
+
+        let mut assembler = parsed(config.clone(), "ldi r1 5\nhlt").unwrap();
+        assert!(assembler.assemble_to_file(path.to_str().unwrap()).is_ok());
+        assert!(!path.exists());
+
+        let mut written = Vec::new();
+        assert!(assembler.assemble_to_writer(&mut written).is_ok());
+        assert!(written.is_empty());
+
+        let assembler = parsed(config, "jmp nowhere").unwrap();
+        assert!(assembler.assemble_to_writer(&mut written).is_err());
+    }
+
+    #[test]
+    fn add_define() {
+        let with_define = |source: &str| {
+            let mut assembler = Assembler::new(AssemblerConfig::default());
+            assembler.add_define("COUNT", "3").unwrap();
+
+            match assembler.parse(source) {
+                Ok(()) => Ok(assembler.assemble().unwrap()),
+                Err(errors) => Err(errors.iter().map(|error| error.to_string()).collect::<Vec<String>>())
+            }
+        };
+
+        assert_eq!(with_define("ldi r1 COUNT"), Ok(vec![0x8103]));
+        assert_eq!(with_define("#define COUNT 4"), Err(vec!["[Line 1] Definition of \"COUNT\" already exists".to_string()]));
+
+        let mut assembler = Assembler::new(AssemblerConfig::default());
+        assert!(assembler.add_define("COUNT", "3").is_ok());
+        assert_eq!(assembler.add_define("COUNT", "4").unwrap_err().to_string(), "Definition of \"COUNT\" already exists");
+        assert!(assembler.add_define("RNG", "1").is_err());
+    }
+
+    #[test]
+    fn label_bytes() {
+        let machine_code = assemble("ldi r1 lo(x)\nadi r2 hi(x)\n.org 300\nx:\nhlt");
+
+        assert_eq!(machine_code[..2], [0x812C, 0x9201]);
+        assert_eq!(machine_code[300], 0x1000);
+        assert_eq!(errors("ldi r1 lo(missing)"), vec!["[Line 1] Label \"missing\" was never defined".to_string()]);
+    }
+
+    #[test]
+    fn trace_descriptions() {
+        let assembler = parsed(AssemblerConfig::default(), ".word 0x12\nldi r1 lo(x)\njmp x+3\nx:").unwrap();
+        let descriptions: Vec<String> = assembler.instructions
+            .iter()
+            .map(|(statement, _, _)| Assembler::describe_statement(statement))
+            .collect();
+
+        assert_eq!(descriptions[0], "Word(0x0012)");
+        assert!(descriptions[1].ends_with(" with lo(x)"));
+        assert!(descriptions[2].ends_with(" with offset +3"));
+    }
+
+    #[test]
+    fn writer_only_gets_output() {
+        let written = |print_info| {
+            let config = AssemblerConfig {
+                print_info,
+                output_format: OutputFormat::Text,
+                ..AssemblerConfig::default()
+            };
+
+            let mut output = Vec::new();
+            parsed(config, "ldi r1 5\nhlt").unwrap().assemble_to_writer(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // Usage goes to stderr, so output written to stdout can be piped
+        assert_eq!(written(true), written(false));
+        assert_eq!(written(true).lines().count(), 2);
+    }
+}
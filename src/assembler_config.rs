@@ -1,8 +1,35 @@
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+use crate::encoding::Word;
+use crate::isa_revision::IsaRevision;
+use crate::output_format::OutputFormat;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AssemblerConfig {
     pub default_defines: bool,
     pub print_info: bool,
-    pub text_output: bool
+    pub output_format: OutputFormat,
+    pub banks: bool,
+    pub allow_memory: bool,
+    pub merge_duplicate_labels: bool,
+    pub style_lints: bool,
+    pub warnings: bool,
+    pub trace_expansion: bool,
+    pub warnings_as_errors: bool,
+    pub build_version: Option<u32>,
+    pub words_per_line: usize,
+    pub listing_output: Option<String>,
+    pub embed_source: bool,
+    pub verify_encoding: bool,
+    pub max_line_length: usize,
+    pub interleave_nops: bool,
+    pub signed_listing: bool,
+    pub isa_revision: IsaRevision,
+    pub allow_reserved_in_macros: bool,
+    pub little_endian: bool,
+    pub ignore_extra_arguments: bool,
+    pub preprocess_only: bool,
+    pub check_only: bool,
+    pub pad_to: Option<usize>,
+    pub pad_word: Word
 }
 
 impl Default for AssemblerConfig {
@@ -10,7 +37,30 @@ impl Default for AssemblerConfig {
         Self {
             default_defines: true,
             print_info: false,
-            text_output: false
+            output_format: OutputFormat::Binary,
+            banks: false,
+            allow_memory: true,
+            merge_duplicate_labels: true,
+            style_lints: false,
+            warnings: false,
+            trace_expansion: false,
+            warnings_as_errors: false,
+            build_version: None,
+            words_per_line: 1,
+            listing_output: None,
+            embed_source: false,
+            verify_encoding: false,
+            max_line_length: 100,
+            interleave_nops: false,
+            signed_listing: false,
+            isa_revision: IsaRevision::V1,
+            allow_reserved_in_macros: true,
+            little_endian: false,
+            ignore_extra_arguments: false,
+            preprocess_only: false,
+            check_only: false,
+            pad_to: None,
+            pad_word: 0
         }
     }
 }
\ No newline at end of file
@@ -6,57 +6,90 @@ use std::fmt::{Display, Formatter};
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AssemblerError {
     pub description: String,
-    pub line: Option<u32>
+    pub line: Option<u32>,
+    pub column: Option<usize>,
+    // Included file the line is in, None for the input itself
+    pub file: Option<String>
 }
 
 impl AssemblerError {
     pub fn new(description: String) -> Self {
         Self {
             description,
-            line: None
+            line: None,
+            column: None,
+            file: None
         }
     }
 
     pub fn new_line(description: String, line: u32) -> Self {
         Self {
             description,
-            line: Some(line)
+            line: Some(line),
+            column: None,
+            file: None
         }
     }
 
     pub fn from_assembly_error(error: &AssemblyError) -> Self {
         Self {
             description: error.description.clone(),
-            line: None
+            line: None,
+            column: None,
+            file: None
         }
     }
 
     pub fn from_assembly_error_line(error: &AssemblyError, line: u32) -> Self {
         Self {
             description: error.description.clone(),
-            line: Some(line)
+            line: Some(line),
+            column: None,
+            file: None
         }
     }
+
+    pub fn with_column(mut self, column: usize) -> Self {
+        self.column = Some(column);
+        self
+    }
+
+    pub fn with_file(mut self, file: String) -> Self {
+        self.file = Some(file);
+        self
+    }
 }
 
 impl Display for AssemblerError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self.line {
-            Some(line) => write!(f, "[Line {}] {}", line, self.description),
-            None => write!(f, "{}", self.description)
+        let Some(line) = self.line else {
+            return match &self.file {
+                Some(file) => write!(f, "[In \"{}\"] {}", file, self.description),
+                None => write!(f, "{}", self.description)
+            };
+        };
+
+        let position = match self.column {
+            Some(column) => format!("{}:{}", line, column),
+            None => line.to_string()
+        };
+
+        match &self.file {
+            Some(file) => write!(f, "[Line {} in \"{}\"] {}", position, file, self.description),
+            None => write!(f, "[Line {}] {}", position, self.description)
         }
     }
 }
 
 impl PartialOrd for AssemblerError {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.line.partial_cmp(&other.line)
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for AssemblerError {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.line.cmp(&other.line)
+        (&self.file, self.line, self.column).cmp(&(&other.file, other.line, other.column))
     }
 }
 
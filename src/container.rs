@@ -0,0 +1,162 @@
+//! Self-describing container for assembled programs. All numbers are big-endian:
+//!
+//! | Size         | Content                                   |
+//! |--------------|-------------------------------------------|
+//! | 4 bytes      | Magic number, `BPUC`                      |
+//! | 1 byte       | Version, currently 1                      |
+//! | 4 bytes      | Instruction count                         |
+//! | 2 bytes each | Machine code, one `Word` per instruction  |
+//! | 4 bytes      | Symbol count                              |
+//! | per symbol   | 2 byte name length, UTF-8 name, 4 byte address |
+
+use crate::assembler_error::AssemblerError;
+use crate::encoding::Word;
+use batpu_assembly::Labels;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+pub const MAGIC: &[u8; 4] = b"BPUC";
+pub const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    pub machine_code: Vec<Word>,
+    pub labels: Labels
+}
+
+impl Container {
+    pub fn new(machine_code: Vec<Word>, labels: Labels) -> Self {
+        Self {
+            machine_code,
+            labels
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+
+        bytes.extend_from_slice(&(self.machine_code.len() as u32).to_be_bytes());
+        for word in &self.machine_code {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+
+        let mut labels: Vec<(&String, &u32)> = self.labels.iter().collect();
+        labels.sort_by(|a, b| a.1.cmp(b.1).then(a.0.cmp(b.0)));
+
+        bytes.extend_from_slice(&(labels.len() as u32).to_be_bytes());
+        for (name, address) in labels {
+            bytes.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.extend_from_slice(&address.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AssemblerError> {
+        let mut reader = Reader {
+            bytes,
+            position: 0
+        };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(AssemblerError::new("Not a container, the magic number is wrong".to_string()));
+        }
+
+        let version = reader.take(1)?[0];
+        if version != VERSION {
+            return Err(AssemblerError::new(format!("Unsupported container version {}", version)));
+        }
+
+        let instruction_count = reader.u32()?;
+        let mut machine_code = Vec::new();
+        for _ in 0..instruction_count {
+            machine_code.push(reader.word()?);
+        }
+
+        let symbol_count = reader.u32()?;
+        let mut labels = HashMap::new();
+        for _ in 0..symbol_count {
+            let name_length = reader.u16()? as usize;
+            let name = match String::from_utf8(reader.take(name_length)?.to_vec()) {
+                Ok(name) => name,
+                Err(_) => return Err(AssemblerError::new("Symbol name is not valid UTF-8".to_string()))
+            };
+
+            let address = reader.u32()?;
+            labels.insert(name, address);
+        }
+
+        if reader.position != bytes.len() {
+            return Err(AssemblerError::new(format!("Container has {} unexpected trailing bytes", bytes.len() - reader.position)));
+        }
+
+        Ok(Self::new(machine_code, labels))
+    }
+
+    pub fn read_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        let bytes = fs::read(path)?;
+        Ok(Self::from_bytes(&bytes)?)
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], AssemblerError> {
+        let end = self.position + length;
+        if end > self.bytes.len() {
+            return Err(AssemblerError::new("Container ended unexpectedly".to_string()));
+        }
+
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+
+        Ok(slice)
+    }
+
+    fn word(&mut self) -> Result<Word, AssemblerError> {
+        let bytes = self.take(size_of::<Word>())?;
+        Ok(Word::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u16(&mut self) -> Result<u16, AssemblerError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, AssemblerError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let container = Container::new(vec![0x8105, 0x1000], Labels::from([("start".to_string(), 0), ("end".to_string(), 2)]));
+        let bytes = container.to_bytes();
+
+        assert_eq!(&bytes[..9], b"BPUC\x01\x00\x00\x00\x02");
+        assert_eq!(Container::from_bytes(&bytes), Ok(container));
+    }
+
+    #[test]
+    fn invalid_bytes() {
+        let bytes = Container::new(vec![0x1000], Labels::new()).to_bytes();
+
+        assert_eq!(Container::from_bytes(b"BPUX").unwrap_err().description, "Not a container, the magic number is wrong");
+        assert_eq!(Container::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err().description, "Container ended unexpectedly");
+        assert_eq!(Container::from_bytes(&[bytes.as_slice(), &[0]].concat()).unwrap_err().description, "Container has 1 unexpected trailing bytes");
+    }
+}
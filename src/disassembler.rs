@@ -0,0 +1,155 @@
+use crate::assembler_error::AssemblerError;
+use crate::encoding;
+use crate::encoding::Word;
+use batpu_assembly::components::address::Address;
+use batpu_assembly::components::condition::Condition;
+use batpu_assembly::components::immediate::Immediate;
+use batpu_assembly::components::location::Location;
+use batpu_assembly::components::offset::Offset;
+use batpu_assembly::components::register::Register;
+use batpu_assembly::instruction::Instruction;
+
+pub fn disassemble(machine_code: &[Word]) -> Result<Vec<Instruction>, AssemblerError> {
+    let mut instructions = Vec::with_capacity(machine_code.len());
+    for (address, &word) in machine_code.iter().enumerate() {
+        match from_binary(word) {
+            Ok(instruction) => instructions.push(instruction),
+            Err(error) => return Err(AssemblerError::new(format!("At address {}: {}", address, error.description)))
+        }
+    }
+
+    Ok(instructions)
+}
+
+pub fn from_binary(word: Word) -> Result<Instruction, AssemblerError> {
+    let mnemonic = encoding::MNEMONICS[encoding::opcode(word) as usize];
+
+    // Bits that the instruction doesn't use, which must be zero
+    let unused_bits = match mnemonic {
+        "nop" | "hlt" | "ret" => 0b1111_1111_1111,
+        "rsh" => 0b1111_0000,
+        "jmp" | "cal" => 0b1100_0000_0000,
+        _ => 0
+    };
+
+    if word & unused_bits != 0 {
+        return Err(AssemblerError::new(format!("Word 0x{:04X} has reserved bits set for \"{}\"", word, mnemonic)));
+    }
+
+    let instruction = match mnemonic {
+        "nop" => Instruction::NoOperation,
+        "hlt" => Instruction::Halt,
+        "add" => Instruction::Addition(register_a(word)?, register_b(word)?, register_c(word)?),
+        "sub" => Instruction::Subtraction(register_a(word)?, register_b(word)?, register_c(word)?),
+        "nor" => Instruction::BitwiseNOR(register_a(word)?, register_b(word)?, register_c(word)?),
+        "and" => Instruction::BitwiseAND(register_a(word)?, register_b(word)?, register_c(word)?),
+        "xor" => Instruction::BitwiseXOR(register_a(word)?, register_b(word)?, register_c(word)?),
+        "rsh" => Instruction::RightShift(register_a(word)?, register_c(word)?),
+        "ldi" => Instruction::LoadImmediate(register_a(word)?, Immediate::new(encoding::immediate(word) as u32)),
+        "adi" => Instruction::AddImmediate(register_a(word)?, Immediate::new(encoding::immediate(word) as u32)),
+        "jmp" => Instruction::Jump(location(word)?),
+        "brh" => Instruction::Branch(condition(word), location(word)?),
+        "cal" => Instruction::Call(location(word)?),
+        "ret" => Instruction::Return,
+        "lod" => Instruction::MemoryLoad(register_a(word)?, register_b(word)?, offset(word)?),
+        _ => Instruction::MemoryStore(register_a(word)?, register_b(word)?, offset(word)?)
+    };
+
+    Ok(instruction)
+}
+
+// Assembly text for a single word, such as "add r1 r2 r3"
+pub fn disassemble_word(word: Word) -> Result<String, AssemblerError> {
+    from_binary(word)?;
+
+    let mnemonic = encoding::MNEMONICS[encoding::opcode(word) as usize];
+
+    let reg_a = format!("r{}", encoding::register_a(word));
+    let reg_b = format!("r{}", encoding::register_b(word));
+    let reg_c = format!("r{}", encoding::register_c(word));
+    let address = encoding::address(word).to_string();
+
+    let operands = match mnemonic {
+        "add" | "sub" | "nor" | "and" | "xor" => vec![reg_a, reg_b, reg_c],
+        "rsh" => vec![reg_a, reg_c],
+        "ldi" | "adi" => vec![reg_a, encoding::immediate(word).to_string()],
+        "jmp" | "cal" => vec![address],
+        "brh" => vec![encoding::CONDITIONS[encoding::condition(word) as usize].to_string(), address],
+        "lod" | "str" => vec![reg_a, reg_b, encoding::offset(word).to_string()],
+        _ => Vec::new()
+    };
+
+    if operands.is_empty() {
+        return Ok(mnemonic.to_string());
+    }
+
+    Ok(format!("{} {}", mnemonic, operands.join(" ")))
+}
+
+fn register(index: Word) -> Result<Register, AssemblerError> {
+    Register::new(index as u32).map_err(|error| AssemblerError::from_assembly_error(&error))
+}
+
+fn register_a(word: Word) -> Result<Register, AssemblerError> {
+    register(encoding::register_a(word))
+}
+
+fn register_b(word: Word) -> Result<Register, AssemblerError> {
+    register(encoding::register_b(word))
+}
+
+fn register_c(word: Word) -> Result<Register, AssemblerError> {
+    register(encoding::register_c(word))
+}
+
+fn location(word: Word) -> Result<Location, AssemblerError> {
+    match Address::new(encoding::address(word) as u32) {
+        Ok(address) => Ok(Location::Address(address)),
+        Err(error) => Err(AssemblerError::from_assembly_error(&error))
+    }
+}
+
+fn condition(word: Word) -> Condition {
+    match encoding::condition(word) {
+        0 => Condition::Zero,
+        1 => Condition::NotZero,
+        2 => Condition::Carry,
+        _ => Condition::NotCarry
+    }
+}
+
+fn offset(word: Word) -> Result<Offset, AssemblerError> {
+    Offset::new(encoding::offset(word) as i32).map_err(|error| AssemblerError::from_assembly_error(&error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use batpu_assembly::Labels;
+
+    #[test]
+    fn round_trip() {
+        let words = [0x0000, 0x1000, 0x2123, 0x7104, 0x8105, 0xA005, 0xB405, 0xD000, 0xE12F, 0xF12F];
+        let instructions = disassemble(&words).unwrap();
+
+        for (address, (instruction, &word)) in instructions.iter().zip(&words).enumerate() {
+            assert_eq!(instruction.binary(address as u32, &Labels::new()).unwrap(), word);
+        }
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(disassemble_word(0x2123).unwrap(), "add r1 r2 r3");
+        assert_eq!(disassemble_word(0x7104).unwrap(), "rsh r1 r4");
+        assert_eq!(disassemble_word(0xB405).unwrap(), "brh notzero 5");
+        assert_eq!(disassemble_word(0xE12F).unwrap(), "lod r1 r2 -1");
+        assert_eq!(disassemble_word(0x1000).unwrap(), "hlt");
+    }
+
+    #[test]
+    fn reserved_bits() {
+        assert_eq!(from_binary(0x1001).unwrap_err().description, "Word 0x1001 has reserved bits set for \"hlt\"");
+        assert_eq!(disassemble(&[0x1000, 0xA400]).unwrap_err().description, "At address 1: Word 0xA400 has reserved bits set for \"jmp\"");
+    }
+}
@@ -0,0 +1,61 @@
+// Width of one instruction or data word, change this to support wider BatPU variants
+pub type Word = u16;
+
+pub const MNEMONICS: [&str; 16] = [
+    "nop", "hlt", "add", "sub", "nor", "and", "xor", "rsh",
+    "ldi", "adi", "jmp", "brh", "cal", "ret", "lod", "str"
+];
+
+// Expanded by the assembler into the instructions above
+pub const PSEUDO_INSTRUCTIONS: [&str; 13] = [
+    "cmp", "mov", "lsh", "inc", "dec", "not", "neg", "or", "nand", "jz", "jnz", "jc", "jnc"
+];
+
+pub const CONDITIONS: [&str; 4] = ["zero", "notzero", "carry", "notcarry"];
+
+// What each condition means after "sub a b", in the same order as CONDITIONS
+pub const CONDITION_ALIASES: [&str; 4] = ["eq", "ne", "ge", "lt"];
+
+pub fn condition_index(name: &str) -> Option<Word> {
+    CONDITIONS
+        .iter()
+        .position(|&condition| condition == name)
+        .or_else(|| CONDITION_ALIASES.iter().position(|&alias| alias == name))
+        .map(|index| index as Word)
+}
+
+pub fn opcode(word: Word) -> Word {
+    word >> 12
+}
+
+pub fn register_a(word: Word) -> Word {
+    (word >> 8) & 0b1111
+}
+
+pub fn register_b(word: Word) -> Word {
+    (word >> 4) & 0b1111
+}
+
+pub fn register_c(word: Word) -> Word {
+    word & 0b1111
+}
+
+pub fn immediate(word: Word) -> Word {
+    word & 0b1111_1111
+}
+
+pub fn condition(word: Word) -> Word {
+    (word >> 10) & 0b11
+}
+
+// Highest address jmp, brh and cal can encode in their 10-bit field
+pub const ADDRESS_MAX: Word = 0b11_1111_1111;
+
+pub fn address(word: Word) -> Word {
+    word & ADDRESS_MAX
+}
+
+pub fn offset(word: Word) -> i16 {
+    // Sign-extend the 4-bit field
+    ((word & 0b1111) as i16) << 12 >> 12
+}
@@ -0,0 +1,160 @@
+// Constant expressions such as "SCR_PIX_X + 2" or "(8 * 3)", with + - * / and parentheses.
+// Numbers and names are resolved by the caller, so they can be defines or labels
+pub fn evaluate(expression: &str, resolve: &mut dyn FnMut(&str) -> Result<i64, String>) -> Result<i64, String> {
+    let mut parser = Parser {
+        expression,
+        tokens: tokenize(expression),
+        position: 0,
+        resolve
+    };
+
+    let value = parser.sum()?;
+
+    if let Some(token) = parser.tokens.get(parser.position) {
+        return Err(format!("Unexpected \"{}\" in expression \"{}\"", token, expression));
+    }
+
+    Ok(value)
+}
+
+fn is_operator(char: char) -> bool {
+    matches!(char, '+' | '-' | '*' | '/' | '(' | ')')
+}
+
+fn tokenize(expression: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (i, char) in expression.char_indices() {
+        if !char.is_whitespace() && !is_operator(char) {
+            start.get_or_insert(i);
+            continue;
+        }
+
+        if let Some(start) = start.take() {
+            tokens.push(&expression[start..i]);
+        }
+
+        if is_operator(char) {
+            tokens.push(&expression[i..i + 1]);
+        }
+    }
+
+    if let Some(start) = start {
+        tokens.push(&expression[start..]);
+    }
+
+    tokens
+}
+
+struct Parser<'a, 'r> {
+    expression: &'a str,
+    tokens: Vec<&'a str>,
+    position: usize,
+    resolve: &'r mut dyn FnMut(&str) -> Result<i64, String>
+}
+
+impl<'a> Parser<'a, '_> {
+    fn next_token(&mut self) -> Option<&'a str> {
+        let token = self.tokens.get(self.position).copied();
+        self.position += 1;
+        token
+    }
+
+    fn peek_token(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn overflow(&self) -> String {
+        format!("Expression \"{}\" overflows", self.expression)
+    }
+
+    // Lowest precedence: a + b - c
+    fn sum(&mut self) -> Result<i64, String> {
+        let mut value = self.product()?;
+
+        while let Some(operator @ ("+" | "-")) = self.peek_token() {
+            let add = operator == "+";
+            self.position += 1;
+
+            let right = self.product()?;
+            let result = if add { value.checked_add(right) } else { value.checked_sub(right) };
+            value = result.ok_or_else(|| self.overflow())?;
+        }
+
+        Ok(value)
+    }
+
+    // a * b / c, division rounds toward zero
+    fn product(&mut self) -> Result<i64, String> {
+        let mut value = self.factor()?;
+
+        while let Some(operator @ ("*" | "/")) = self.peek_token() {
+            let multiply = operator == "*";
+            self.position += 1;
+
+            let right = self.factor()?;
+            value = if multiply {
+                value.checked_mul(right).ok_or_else(|| self.overflow())?
+            } else {
+                if right == 0 {
+                    return Err(format!("Division by zero in expression \"{}\"", self.expression));
+                }
+
+                value.checked_div(right).ok_or_else(|| self.overflow())?
+            };
+        }
+
+        Ok(value)
+    }
+
+    fn factor(&mut self) -> Result<i64, String> {
+        let expression = self.expression;
+
+        match self.next_token() {
+            Some("-") => self.factor()?.checked_neg().ok_or_else(|| self.overflow()),
+            Some("+") => self.factor(),
+            Some("(") => {
+                let value = self.sum()?;
+
+                match self.next_token() {
+                    Some(")") => Ok(value),
+                    _ => Err(format!("Expression \"{}\" is missing a closing parenthesis", expression))
+                }
+            },
+            Some(token) if !token.starts_with(is_operator) => (self.resolve)(token),
+            Some(token) => Err(format!("Unexpected \"{}\" in expression \"{}\"", token, expression)),
+            None => Err(format!("Expression \"{}\" ends before a value", expression))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(expression: &str) -> Result<i64, String> {
+        evaluate(expression, &mut |token| match token {
+            "WIDTH" => Ok(10),
+            _ => token.parse().map_err(|_| format!("\"{}\" isn't a define or a label", token))
+        })
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(value("2 + 3 * 4"), Ok(14));
+        assert_eq!(value("(2+3)*4"), Ok(20));
+        assert_eq!(value("WIDTH - 2 - 3"), Ok(5));
+        assert_eq!(value("-7 / 2"), Ok(-3));
+        assert_eq!(value("-(WIDTH*2)"), Ok(-20));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(value("1 / (WIDTH - 10)"), Err("Division by zero in expression \"1 / (WIDTH - 10)\"".to_string()));
+        assert_eq!(value("(1 + 2"), Err("Expression \"(1 + 2\" is missing a closing parenthesis".to_string()));
+        assert_eq!(value("1 +"), Err("Expression \"1 +\" ends before a value".to_string()));
+        assert_eq!(value("1 2"), Err("Unexpected \"2\" in expression \"1 2\"".to_string()));
+        assert_eq!(value("HEIGHT + 1"), Err("\"HEIGHT\" isn't a define or a label".to_string()));
+    }
+}
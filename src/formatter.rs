@@ -0,0 +1,147 @@
+use crate::assembler::Assembler;
+
+const INDENT: &str = "  ";
+// Fits the longest mnemonic, "nand"
+const MNEMONIC_WIDTH: usize = 4;
+// Fits the longest register, "r15"
+const OPERAND_WIDTH: usize = 3;
+const COMMENT_COLUMN: usize = 32;
+
+/// Re-emits assembly source in a canonical layout:
+/// - Labels and `#` directives start at column 0
+/// - Other statements and comment-only lines are indented by two spaces once a label has been seen
+/// - Mnemonics and pseudo-instructions are lowercased and padded to four columns, and every operand but the last is
+///   padded to three columns, so registers line up; longer operands are followed by a single space
+/// - Other statements, such as directives and macros, keep their case and have their operands separated by a single space
+/// - Statements on the same line are separated by `; `
+/// - Trailing comments start at column 32, or one space after the code if it's longer
+/// - Blank lines are kept and trailing whitespace is removed
+pub fn format_source(input: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_label = false;
+
+    for line in input.lines() {
+        let (code, comment) = match Assembler::comment_index(line) {
+            Some(index) => (&line[..index], Some(line[index + 2..].trim())),
+            None => (line, None)
+        };
+
+        let statements: Vec<String> = Assembler::split_unquoted(code, |c| c == ';')
+            .into_iter()
+            .map(|statement| statement.trim())
+            .filter(|statement| !statement.is_empty())
+            .map(format_statement)
+            .collect();
+
+        let unindented = match statements.first() {
+            Some(statement) => statement.ends_with(':') || statement.starts_with('#'),
+            None => false
+        };
+
+        if statements.first().is_some_and(|statement| statement.ends_with(':')) {
+            in_label = true;
+        }
+
+        let indent = if in_label && !unindented { INDENT } else { "" };
+
+        let mut formatted = String::new();
+        if !statements.is_empty() {
+            formatted.push_str(indent);
+            formatted.push_str(&statements.join("; "));
+        }
+
+        if let Some(comment) = comment {
+            let comment = if comment.is_empty() {
+                "//".to_string()
+            } else {
+                format!("// {}", comment)
+            };
+
+            if formatted.is_empty() {
+                formatted.push_str(indent);
+            } else {
+                let padding = COMMENT_COLUMN.saturating_sub(formatted.chars().count()).max(1);
+                formatted.push_str(&" ".repeat(padding));
+            }
+
+            formatted.push_str(&comment);
+        }
+
+        lines.push(formatted);
+    }
+
+    let mut output = lines.join("\n");
+    if !output.is_empty() {
+        output.push('\n');
+    }
+
+    output
+}
+
+fn format_statement(statement: &str) -> String {
+    // Expressions such as "SCR_PIX_X + 2" stay one operand, and spaces inside literals such as ".string "a  b"" are part of the data
+    let arguments = Assembler::split_arguments(statement);
+
+    let Some((&name, operands)) = arguments.split_first() else {
+        return String::new();
+    };
+
+    // Labels, directives and macro names are case-sensitive, so they're kept as-is
+    let Some(name) = Assembler::instruction_name(name) else {
+        return arguments.join(" ");
+    };
+
+    let Some((last, operands)) = operands.split_last() else {
+        return name;
+    };
+
+    let mut formatted = format!("{:<width$} ", name, width = MNEMONIC_WIDTH);
+    for operand in operands {
+        formatted.push_str(&format!("{:<width$} ", operand, width = OPERAND_WIDTH));
+    }
+
+    formatted.push_str(last);
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn golden() {
+        let input = concat!(
+            "#define  X   5\n",
+            "start:\n",
+            "LDI r1   X // load\n",
+            "   add r1 r2 r3;hlt\n",
+            ".string \"HI  THERE\" // text\n",
+            "\n",
+            "  // done   \n",
+            "brh   zero start\n"
+        );
+
+        let expected = concat!(
+            "#define X 5\n",
+            "start:\n",
+            "  ldi  r1  X                    // load\n",
+            "  add  r1  r2  r3; hlt\n",
+            "  .string \"HI  THERE\"           // text\n",
+            "\n",
+            "  // done\n",
+            "  brh  zero start\n"
+        );
+
+        assert_eq!(format_source(input), expected);
+    }
+
+    #[test]
+    fn comment_inside_literal() {
+        assert_eq!(format_source(".string \"A//B\"\n"), ".string \"A//B\"\n");
+    }
+
+    #[test]
+    fn macro_names_keep_their_case() {
+        assert_eq!(format_source("Add_To r1 10\n"), "Add_To r1 10\n");
+    }
+}
@@ -0,0 +1,13 @@
+use batpu_assembly::instruction::Instruction;
+
+pub trait InstructionValidator {
+    fn validate(&self, address: u32, instruction: &Instruction) -> Result<(), String>;
+}
+
+pub struct NoOpValidator;
+
+impl InstructionValidator for NoOpValidator {
+    fn validate(&self, _address: u32, _instruction: &Instruction) -> Result<(), String> {
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IsaRevision {
+    V1,
+    V2
+}
+
+impl IsaRevision {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "v1" => Some(Self::V1),
+            "v2" => Some(Self::V2),
+            _ => None
+        }
+    }
+
+    // Both revisions have the same instructions, only the names of the ports differ
+    pub fn default_defines(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::V1 => V1_DEFINES,
+            Self::V2 => V2_DEFINES
+        }
+    }
+}
+
+const V1_DEFINES: &[(&str, &str)] = &[
+    // Screen
+
+    ("SCR_PIX_X", "240"),
+    ("SCR_PIX_Y", "241"),
+
+    ("SCR_DRAW_PIX", "242"),
+    ("SCR_CLR_PIX", "243"),
+    ("SCR_GET_PIX", "244"),
+
+    ("SCR_PUSH", "245"),
+    ("SCR_CLR", "246"),
+
+    // Character Display
+
+    ("CHAR_DISP_PUSH", "247"),
+
+    ("CHAR_DISP_DRAW", "248"),
+    ("CHAR_DISP_CLR", "249"),
+
+    // Number Display
+
+    ("NUM_DISP_SHOW", "250"),
+    ("NUM_DISP_CLR", "251"),
+
+    ("NUM_DISP_SIGNED", "252"),
+    ("NUM_DISP_UNSIGNED", "253"),
+
+    // Random Number Generator
+    ("RNG", "254"),
+
+    // Controller
+    ("CONTROLLER", "255")
+];
+
+// Port names as used by the published BatPU-2 programs
+const V2_DEFINES: &[(&str, &str)] = &[
+    // Screen
+
+    ("SCR_PIX_X", "240"),
+    ("SCR_PIX_Y", "241"),
+
+    ("SCR_DRAW_PIX", "242"),
+    ("SCR_CLR_PIX", "243"),
+    ("SCR_GET_PIX", "244"),
+
+    ("SCR_DRAW", "245"),
+    ("SCR_CLR", "246"),
+
+    // Character Display
+
+    ("CHAR_DISP_WRITE", "247"),
+
+    ("CHAR_DISP_PUSH", "248"),
+    ("CHAR_DISP_CLR", "249"),
+
+    // Number Display
+
+    ("NUM_DISP_SHOW", "250"),
+    ("NUM_DISP_CLR", "251"),
+
+    ("NUM_DISP_SIGNED", "252"),
+    ("NUM_DISP_UNSIGNED", "253"),
+
+    // Random Number Generator
+    ("RNG", "254"),
+
+    // Controller
+    ("CONTROLLER", "255")
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_name() {
+        assert_eq!(IsaRevision::from_name("v1"), Some(IsaRevision::V1));
+        assert_eq!(IsaRevision::from_name("V2"), Some(IsaRevision::V2));
+        assert_eq!(IsaRevision::from_name("v3"), None);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::assembler_error::AssemblerError;
+use std::ops::Range;
+
+pub struct PinnedRegion {
+    pub address: usize,
+    pub statements: Range<usize>,
+    pub line: u32
+}
+
+pub enum Slot {
+    Empty,
+    Statement(usize),
+    Jump(usize)
+}
+
+pub struct Layout {
+    pub slots: Vec<Slot>,
+    /// New address of every statement, indexed by its original position
+    pub addresses: Vec<usize>,
+    /// Address right after the last unpinned statement
+    pub end_address: usize
+}
+
+/// Places pinned regions at their addresses, then fills the remaining space with the unpinned
+/// statements in order. When unpinned code runs into a pinned region, a jump to the next free
+/// address is inserted, so execution continues after the pinned region instead of running into it.
+pub fn layout(statement_count: usize, regions: &[PinnedRegion], max_size: usize) -> Result<Layout, AssemblerError> {
+    let mut slots = Vec::new();
+    let mut addresses = vec![0; statement_count];
+    let mut pinned = vec![false; statement_count];
+
+    let mut sorted_regions: Vec<&PinnedRegion> = regions.iter().collect();
+    sorted_regions.sort_by_key(|region| region.address);
+
+    for pair in sorted_regions.windows(2) {
+        if pair[0].address + pair[0].statements.len() > pair[1].address {
+            return Err(AssemblerError::new_line(format!("Pinned region at address {} overlaps the pinned region at address {}", pair[1].address, pair[0].address), pair[1].line));
+        }
+    }
+
+    for region in regions {
+        if region.address + region.statements.len() > max_size {
+            return Err(AssemblerError::new_line(format!("Pinned region at address {} doesn't fit in the program ({} instructions)", region.address, max_size), region.line));
+        }
+
+        for (offset, index) in region.statements.clone().enumerate() {
+            place(&mut slots, region.address + offset, Slot::Statement(index));
+            addresses[index] = region.address + offset;
+            pinned[index] = true;
+        }
+    }
+
+    let unpinned: Vec<usize> = (0..statement_count)
+        .filter(|&index| !pinned[index])
+        .collect();
+
+    let mut address = 0;
+    for &index in &unpinned {
+        address = next_free(&slots, address);
+
+        // Jump over pinned code, even after the last statement, which would otherwise run into it
+        while !is_free(&slots, address + 1) {
+            let resume_address = next_free(&slots, address + 1);
+            place(&mut slots, address, Slot::Jump(resume_address));
+            address = resume_address;
+        }
+
+        place(&mut slots, address, Slot::Statement(index));
+        addresses[index] = address;
+        address += 1;
+    }
+
+    if slots.len() > max_size {
+        return Err(AssemblerError::new(format!("Program doesn't fit around pinned regions ({} instructions)", max_size)));
+    }
+
+    Ok(Layout {
+        slots,
+        addresses,
+        end_address: address
+    })
+}
+
+fn is_free(slots: &[Slot], address: usize) -> bool {
+    matches!(slots.get(address), None | Some(Slot::Empty))
+}
+
+fn next_free(slots: &[Slot], mut address: usize) -> usize {
+    while !is_free(slots, address) {
+        address += 1;
+    }
+
+    address
+}
+
+fn place(slots: &mut Vec<Slot>, address: usize, slot: Slot) {
+    if slots.len() <= address {
+        slots.resize_with(address + 1, || Slot::Empty);
+    }
+
+    slots[address] = slot;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(address: usize, statements: Range<usize>) -> PinnedRegion {
+        PinnedRegion {
+            address,
+            statements,
+            line: 1
+        }
+    }
+
+    #[test]
+    fn two_pins() {
+        let layout = layout(6, &[region(0, 0..2), region(4, 2..3)], 16).unwrap();
+
+        assert_eq!(layout.addresses, vec![0, 1, 4, 2, 5, 6]);
+        assert!(matches!(layout.slots[3], Slot::Jump(5)));
+        assert_eq!(layout.end_address, 7);
+    }
+
+    #[test]
+    fn last_statement_jumps_over_pin() {
+        let layout = layout(2, &[region(1, 0..1)], 16).unwrap();
+
+        assert!(matches!(layout.slots[0], Slot::Jump(2)));
+        assert_eq!(layout.addresses, vec![1, 2]);
+    }
+
+    #[test]
+    fn overlapping_pins() {
+        assert!(layout(4, &[region(0, 0..2), region(1, 2..4)], 16).is_err());
+    }
+}
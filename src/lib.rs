@@ -1,3 +1,15 @@
 pub mod assembler_error;
 pub mod assembler_config;
-pub mod assembler;
\ No newline at end of file
+pub mod assembler;
+pub mod output_format;
+pub mod isa_revision;
+pub mod formatter;
+pub mod container;
+pub mod instruction_validator;
+pub mod encoding;
+pub mod sexpr;
+pub mod disassembler;
+pub mod simulator;
+pub mod tokenizer;
+mod layout;
+mod expression;
\ No newline at end of file
@@ -3,10 +3,22 @@ mod argument_error;
 use crate::argument_error::ArgumentError;
 use batpu_assembler::assembler::Assembler;
 use batpu_assembler::assembler_config::AssemblerConfig;
+use batpu_assembler::encoding::Word;
+use batpu_assembler::formatter;
+use batpu_assembler::isa_revision::IsaRevision;
+use batpu_assembler::output_format::OutputFormat;
+use batpu_assembler::simulator;
+use batpu_assembler::simulator::Simulator;
 use std::env;
 use std::error::Error;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
 use std::process::ExitCode;
 
+const MAX_RUN_STEPS: u64 = 10_000_000;
+
 fn main() -> ExitCode {
     let mut config = AssemblerConfig::default();
     config.print_info = true;
@@ -18,9 +30,26 @@ fn main() -> ExitCode {
 
     let mut arg_errors: Vec<Box<dyn Error>> = Vec::new();
     let mut help = false;
+    let mut format_specified = false;
+    let mut profile_size = false;
+    let mut longest_block = false;
+    let mut format_source = false;
+    let mut port_usage = false;
+    let mut run = false;
+    let mut diff_source = false;
+
+    let mut inputs: Vec<u8> = Vec::new();
+    let mut seed = simulator::DEFAULT_SEED;
+
+    let mut defines: Vec<(&str, &str)> = Vec::new();
+    let mut export_defines: Option<&str> = None;
+    let mut symbols: Option<&str> = None;
+    let mut only: Option<&str> = None;
 
-    for arg in args.iter().skip(1) {
-        if !arg.starts_with("-") {
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        // A lone "-" is stdin or stdout, not an option
+        if !arg.starts_with("-") || arg == "-" {
             values.push(arg);
             continue;
         }
@@ -38,7 +67,223 @@ fn main() -> ExitCode {
                 config.print_info = false;
             },
             "-t" | "--text-output" => {
-                config.text_output = true;
+                config.output_format = OutputFormat::Text;
+                format_specified = true;
+            },
+            "-X" | "--hex-output" => {
+                config.output_format = OutputFormat::Hex;
+                format_specified = true;
+            },
+            "-W" | "--words-per-line" => {
+                match arg_iter.next().map(|count| count.parse::<usize>()) {
+                    Some(Ok(count)) if count > 0 => config.words_per_line = count,
+                    _ => {
+                        arg_errors.push(ArgumentError::new(format!("Option \"{}\" expects a number above 0", arg)).into());
+                        continue;
+                    }
+                }
+            },
+            "-E" | "--embed-source" => {
+                config.embed_source = true;
+            },
+            "-l" | "--listing" => {
+                config.output_format = OutputFormat::Listing;
+                format_specified = true;
+            },
+            "-g" | "--signed-listing" => {
+                config.signed_listing = true;
+            },
+            "-A" | "--isa" => {
+                match arg_iter.next().and_then(|revision| IsaRevision::from_name(revision)) {
+                    Some(revision) => config.isa_revision = revision,
+                    None => {
+                        arg_errors.push(ArgumentError::new(format!("Option \"{}\" expects an ISA revision, v1 or v2", arg)).into());
+                        continue;
+                    }
+                }
+            },
+            "-m" | "--no-memory" => {
+                config.allow_memory = false;
+            },
+            "-s" | "--profile-size" => {
+                profile_size = true;
+            },
+            "-k" | "--longest-block" => {
+                longest_block = true;
+            },
+            "-f" | "--format-source" => {
+                format_source = true;
+            },
+            "-o" | "--listing-output" => {
+                match arg_iter.next() {
+                    Some(path) => config.listing_output = Some(path.to_string()),
+                    None => {
+                        arg_errors.push(ArgumentError::new(format!("Option \"{}\" expects a file", arg)).into());
+                        continue;
+                    }
+                }
+            },
+            "-O" | "--only" => {
+                match arg_iter.next() {
+                    Some(label) => only = Some(label),
+                    None => {
+                        arg_errors.push(ArgumentError::new(format!("Option \"{}\" expects a label", arg)).into());
+                        continue;
+                    }
+                }
+            },
+            "-D" | "--define" => {
+                // Like a C compiler, "-D DEBUG" is the same as "-D DEBUG=1"
+                match arg_iter.next().map(|define| define.split_once('=').unwrap_or((define, "1"))) {
+                    Some((name, value)) if !name.is_empty() && !value.is_empty() => defines.push((name, value)),
+                    _ => arg_errors.push(ArgumentError::new(format!("Option \"{}\" expects NAME=VALUE", arg)).into())
+                }
+
+                // Can be given more than once
+                continue;
+            },
+            "-e" | "--export-defines" => {
+                match arg_iter.next() {
+                    Some(path) => export_defines = Some(path),
+                    None => {
+                        arg_errors.push(ArgumentError::new(format!("Option \"{}\" expects a file", arg)).into());
+                        continue;
+                    }
+                }
+            },
+            "-Y" | "--symbols" => {
+                match arg_iter.next() {
+                    Some(path) => symbols = Some(path),
+                    None => {
+                        arg_errors.push(ArgumentError::new(format!("Option \"{}\" expects a file", arg)).into());
+                        continue;
+                    }
+                }
+            },
+            "-j" | "--ignore-extra-arguments" => {
+                config.ignore_extra_arguments = true;
+            },
+            "-w" | "--warnings" => {
+                config.warnings = true;
+            },
+            "-y" | "--style-lints" => {
+                config.style_lints = true;
+            },
+            "-r" | "--report" => {
+                config.output_format = OutputFormat::Report;
+                format_specified = true;
+            },
+            "-u" | "--port-usage" => {
+                port_usage = true;
+            },
+            "-S" | "--strict" => {
+                config.warnings = true;
+                config.warnings_as_errors = true;
+            },
+            "-M" | "--max-line-length" => {
+                match arg_iter.next().map(|length| length.parse::<usize>()) {
+                    Some(Ok(length)) => config.max_line_length = length,
+                    _ => {
+                        arg_errors.push(ArgumentError::new(format!("Option \"{}\" expects a number", arg)).into());
+                        continue;
+                    }
+                }
+            },
+            "-x" | "--trace-expansion" => {
+                config.trace_expansion = true;
+            },
+            "-P" | "--expand" => {
+                config.preprocess_only = true;
+            },
+            "-C" | "--check" => {
+                config.check_only = true;
+            },
+            "-c" | "--container" => {
+                config.output_format = OutputFormat::Container;
+                format_specified = true;
+            },
+            "-a" | "--sexpr" => {
+                config.output_format = OutputFormat::SExpr;
+                format_specified = true;
+            },
+            "-v" | "--build-version" => {
+                match arg_iter.next().map(|version| version.parse::<u32>()) {
+                    Some(Ok(version)) => config.build_version = Some(version),
+                    _ => {
+                        arg_errors.push(ArgumentError::new(format!("Option \"{}\" expects a number", arg)).into());
+                        continue;
+                    }
+                }
+            },
+            "-G" | "--diff-source" => {
+                diff_source = true;
+            },
+            "-R" | "--run" => {
+                run = true;
+            },
+            "-i" | "--input" => {
+                let parsed: Option<Result<Vec<u8>, _>> = arg_iter
+                    .next()
+                    .map(|values| values.split(',').map(|value| value.trim().parse::<u8>()).collect());
+
+                match parsed {
+                    Some(Ok(values)) => inputs = values,
+                    _ => {
+                        arg_errors.push(ArgumentError::new(format!("Option \"{}\" expects comma-separated numbers from 0 to 255", arg)).into());
+                        continue;
+                    }
+                }
+            },
+            "-J" | "--playground-json" => {
+                config.output_format = OutputFormat::PlaygroundJson;
+                format_specified = true;
+            },
+            "-q" | "--mif" => {
+                config.output_format = OutputFormat::Mif;
+                format_specified = true;
+            },
+            "-L" | "--logisim" => {
+                config.output_format = OutputFormat::Logisim;
+                format_specified = true;
+            },
+            "-n" | "--seed" => {
+                match arg_iter.next().map(|seed| seed.parse::<u32>()) {
+                    Some(Ok(value)) => seed = value,
+                    _ => {
+                        arg_errors.push(ArgumentError::new(format!("Option \"{}\" expects a number", arg)).into());
+                        continue;
+                    }
+                }
+            },
+            "-V" | "--verify-encoding" => {
+                config.verify_encoding = true;
+            },
+            "-I" | "--interleave-nops" => {
+                config.interleave_nops = true;
+            },
+            "-N" | "--little-endian" => {
+                config.little_endian = true;
+            },
+            "-z" | "--pad-to" => {
+                match arg_iter.next().map(|size| size.parse::<usize>()) {
+                    Some(Ok(size)) => config.pad_to = Some(size),
+                    _ => {
+                        arg_errors.push(ArgumentError::new(format!("Option \"{}\" expects a number", arg)).into());
+                        continue;
+                    }
+                }
+            },
+            "-F" | "--pad-word" => {
+                match arg_iter.next().map(|word| word.parse::<Word>()) {
+                    Some(Ok(word)) => config.pad_word = word,
+                    _ => {
+                        arg_errors.push(ArgumentError::new(format!("Option \"{}\" expects a number from 0 to {}", arg, Word::MAX)).into());
+                        continue;
+                    }
+                }
+            },
+            "-b" | "--banks" => {
+                config.banks = true;
             },
             "-h" |  "--help" => {
                 help = true;
@@ -64,46 +309,455 @@ fn main() -> ExitCode {
         println!("batpu-assembler v{}
 Usage: batpu-assembler [INPUT] [OUTPUT]
 -d, --disable-default-defines - Disables built-in defines, such as SCR_PIX_X
+-A, --isa [REVISION]          - ISA revision to take built-in defines from, v1 (default) or v2
 -p, --no-print-info           - Do not print assembler info
--t, --text-output             - Assemble to text file with binary representation", env!("CARGO_PKG_VERSION"));
+-t, --text-output             - Assemble to text file with binary representation
+-X, --hex-output              - Assemble to text file with hexadecimal representation
+-W, --words-per-line [N]      - Number of words on each line of text and hexadecimal output, 1 by default
+-E, --embed-source            - Append the source as comments to text and hexadecimal output
+-l, --listing                 - Assemble to listing file with addresses, defines and labels
+-g, --signed-listing          - Show every word as a signed decimal in listings, such as -1 for 0xFFFF
+-r, --report                  - Assemble to text file with binary representation, symbols and stats
+-c, --container               - Assemble to container file with a header and symbol table
+-a, --sexpr                   - Assemble to s-expressions, such as (add (reg 1) (reg 2) (reg 3))
+-L, --logisim                 - Assemble to a Logisim memory image (v2.0 raw)
+-q, --mif                     - Assemble to a memory initialization file for FPGA tools
+-J, --playground-json         - Assemble to JSON with the words, labels and source line of every word
+-V, --verify-encoding         - Check that every instruction was encoded with the right opcode and no stray bits
+-I, --interleave-nops         - Put a nop after every instruction, for debugging hardware timing
+-N, --little-endian           - Write binary output with the low byte of every word first
+-b, --banks                   - Split programs that exceed the ROM into multiple bank files
+-z, --pad-to [N]              - Fill the output up to N words, for loaders that expect an image of a fixed size
+-F, --pad-word [WORD]         - Word to fill the output with for --pad-to, 0 (nop) by default
+-m, --no-memory               - Disallow memory instructions (lod and str)
+-s, --profile-size            - Print the number of instructions under each label, largest first
+-k, --longest-block           - Print the longest run of instructions without jumps in or out of it
+-f, --format-source           - Print the input in a canonical layout, or write it to OUTPUT if given
+-v, --build-version [N]       - Define __VERSION__ as N, such as for showing it on the number display
+-o, --listing-output [FILE]   - Also write a listing of every source line with its addresses and words to FILE
+-O, --only [LABEL]            - Only assemble the code from LABEL up to the next label, with LABEL at address 0
+-D, --define [NAME=VALUE]     - Define NAME as VALUE before the source, or as 1 without a value. Can be given more than once
+-e, --export-defines [FILE]   - Write every label as a "#define LABEL address" line to FILE
+-Y, --symbols [FILE]          - Write every label as a "LABEL = 0x0123" line to FILE, sorted by address
+-u, --port-usage              - Print which built-in defines are used by the program
+-j, --ignore-extra-arguments  - Warn about arguments after the ones an instruction takes, instead of failing
+-w, --warnings                - Warn about code that is likely a mistake or could be clearer
+-y, --style-lints             - Warn about style issues, such as leading zeros in registers (r05)
+-M, --max-line-length [N]     - Maximum line length for --style-lints, 100 by default
+-S, --strict                  - Enable all warnings and treat them as errors, style lints stay warnings
+-R, --run                     - Assemble and simulate INPUT, then print the registers once it halts
+-G, --diff-source             - Assemble OLD and NEW inputs, then print every changed address with its source lines
+-i, --input [VALUES]          - Comma-separated values read from the controller port by --run
+-n, --seed [N]                - Seed for the RNG port in --run, so runs can be repeated
+-x, --trace-expansion         - Print every statement with the instructions it expands to
+-P, --expand                  - Write the source with every define substituted and macro expanded to OUTPUT, without assembling
+-C, --check                   - Assemble INPUT without writing the output or any other file, only reporting errors through the exit code", env!("CARGO_PKG_VERSION"));
         return ExitCode::SUCCESS;
     }
     
-    if values.len() != 2 {
+    if format_source {
+        return format_file(config, &values, &defines);
+    }
+
+    if run {
+        return run_file(config, &values, &defines, inputs, seed);
+    }
+
+    if diff_source {
+        return diff_files(config, &values, &defines);
+    }
+    
+    if config.check_only {
+        if values.len() != 1 {
+            eprintln!("Expected only an input file, got {} value(s)", values.len());
+            return ExitCode::FAILURE;
+        }
+    } else if values.len() != 2 {
         eprintln!("Expected input and output files, got {} value(s)", values.len());
         return ExitCode::FAILURE;
     }
     
     let input_path = &values[0];
-    let output_path = &values[1];
+    // Nothing is written when only checking
+    let output_path = values.get(1).unwrap_or(&"-");
+
+    if !format_specified {
+        config.output_format = match OutputFormat::from_extension(output_path) {
+            Some(format) => format,
+            None => {
+                // Such as ".hx" instead of ".hex", which would otherwise quietly be raw binary
+                if let Some(extension) = Path::new(output_path).extension() {
+                    eprintln!("Warning: Unknown output extension \".{}\", assembling to raw binary", extension.to_string_lossy());
+                }
+
+                OutputFormat::Binary
+            }
+        };
+    }
 
     let mut assembler = Assembler::new(config);
     
-    let parse_result = assembler.parse_file(input_path);
+    let parse_result = parse_input(&mut assembler, input_path, &defines);
+
+    for warning in assembler.warnings() {
+        eprintln!("Warning: {}", warning);
+    }
+
     if let Err(errors) = parse_result {
-        eprintln!("Failed to assemble \"{}\":", input_path);
-        for error in &errors {
-            eprintln!("{}", error);
-        }
-        eprintln!("{} error{}", errors.len(), if errors.len() == 1 { "" } else { "s" });
-        
+        print_errors(input_path, &errors);
         return ExitCode::FAILURE;
     }
 
-    let assemble_result = assembler.assemble_to_file(output_path);
-    if let Err(errors) = assemble_result {
-        eprintln!("Failed to assemble \"{}\":", input_path);
-        for error in &errors {
-            eprintln!("{}", error);
+    if assembler.config.preprocess_only {
+        let expanded = assembler.expanded_source();
+
+        let write_result = if *output_path == "-" {
+            io::stdout().lock().write_all(expanded.as_bytes())
+        } else {
+            fs::write(output_path, expanded)
+        };
+
+        if let Err(error) = write_result {
+            eprintln!("Failed to write \"{}\": {}", output_path, error);
+            return ExitCode::FAILURE;
+        }
+
+        if assembler.config.print_info {
+            eprintln!("Expanded \"{}\" to \"{}\"", input_path, output_path);
+        }
+
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(label) = only {
+        assembler = match assembler.region(label) {
+            Ok(region) => region,
+            Err(errors) => {
+                let errors: Vec<Box<dyn Error>> = errors
+                    .into_iter()
+                    .map(|error| error.into())
+                    .collect();
+
+                print_errors(input_path, &errors);
+                return ExitCode::FAILURE;
+            }
+        };
+    }
+
+    if profile_size {
+        for (label, size) in assembler.size_profile() {
+            eprintln!("{}: {} instruction{}", label, size, if size == 1 { "" } else { "s" });
         }
-        eprintln!("{} error{}", errors.len(), if errors.len() == 1 { "" } else { "s" });
+    }
+
+    if longest_block {
+        match assembler.longest_basic_block() {
+            Some((start, length)) => eprintln!(
+                "Longest basic block: {} instruction{} from address {} to {}",
+                length,
+                if length == 1 { "" } else { "s" },
+                start,
+                start + length - 1
+            ),
+            None => eprintln!("Longest basic block: none")
+        }
+    }
+
+    if port_usage {
+        for (name, value, used) in assembler.port_usage() {
+            eprintln!("{} ({}): {}", name, value, if used { "used" } else { "unused" });
+        }
+    }
+
+    // Banked programs are fine here, and the listing, exported labels and symbols aren't written
+    if assembler.config.check_only {
+        if let Err(errors) = assembler.assemble() {
+            let errors: Vec<Box<dyn Error>> = errors
+                .into_iter()
+                .map(|error| error.into())
+                .collect();
+
+            print_errors(input_path, &errors);
+            return ExitCode::FAILURE;
+        }
+
+        if assembler.config.print_info {
+            assembler.print_usage();
+            eprintln!("Checked \"{}\"", input_path);
+        }
+
+        return ExitCode::SUCCESS;
+    }
 
+    let assemble_result = if *output_path == "-" {
+        let mut stdout = io::stdout().lock();
+        assembler
+            .assemble_to_writer(&mut stdout)
+            .and_then(|_| stdout.flush().map_err(|error| vec![error.into()]))
+    } else {
+        assembler.assemble_to_file(output_path)
+    };
+    if let Err(errors) = assemble_result {
+        print_errors(input_path, &errors);
         return ExitCode::FAILURE;
     }
 
-    if config.print_info {
-        println!("Assembled \"{}\" to \"{}\"", input_path, output_path);
+    if let Some(defines_path) = export_defines {
+        if let Err(error) = assembler.write_label_defines(defines_path) {
+            eprintln!("Failed to export labels to \"{}\": {}", defines_path, error);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Some(symbols_path) = symbols {
+        if let Err(error) = assembler.write_symbols(symbols_path) {
+            eprintln!("Failed to write symbols to \"{}\": {}", symbols_path, error);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if assembler.config.print_info {
+        assembler.print_usage();
+        eprintln!("Assembled \"{}\" to \"{}\"", input_path, output_path);
     }
     
     ExitCode::SUCCESS
+}
+
+fn format_file(config: AssemblerConfig, values: &[&str], defines: &[(&str, &str)]) -> ExitCode {
+    if values.len() > 2 {
+        eprintln!("Expected input and optional output file, got {} value(s)", values.len());
+        return ExitCode::FAILURE;
+    }
+
+    let input_path = values[0];
+
+    let source = match fs::read_to_string(input_path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Failed to read \"{}\": {}", input_path, error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let formatted = formatter::format_source(&source);
+
+    // Make sure the formatted source still assembles to the same machine code
+    let assemble = |source: &str| -> Result<Vec<Word>, Vec<Box<dyn Error>>> {
+        let mut assembler = Assembler::new(config.clone());
+        add_defines(&mut assembler, defines)?;
+        assembler.parse_with_overlay(input_path, source)?;
+
+        assembler.assemble().map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|error| error.into())
+                .collect()
+        })
+    };
+
+    let original = match assemble(&source) {
+        Ok(machine_code) => machine_code,
+        Err(errors) => {
+            print_errors(input_path, &errors);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match assemble(&formatted) {
+        Ok(machine_code) if machine_code == original => {},
+        Ok(_) => {
+            eprintln!("Formatting \"{}\" would change its machine code, so it was left as-is", input_path);
+            return ExitCode::FAILURE;
+        },
+        Err(errors) => {
+            print_errors(input_path, &errors);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    match values.get(1) {
+        Some(output_path) => {
+            if let Err(error) = fs::write(output_path, formatted) {
+                eprintln!("Failed to write \"{}\": {}", output_path, error);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => print!("{}", formatted)
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn run_file(config: AssemblerConfig, values: &[&str], defines: &[(&str, &str)], inputs: Vec<u8>, seed: u32) -> ExitCode {
+    if values.len() != 1 {
+        eprintln!("Expected only an input file, got {} value(s)", values.len());
+        return ExitCode::FAILURE;
+    }
+
+    let input_path = values[0];
+
+    let mut assembler = Assembler::new(config);
+    let parse_result = parse_input(&mut assembler, input_path, defines);
+
+    for warning in assembler.warnings() {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if let Err(errors) = parse_result {
+        print_errors(input_path, &errors);
+        return ExitCode::FAILURE;
+    }
+
+    let machine_code = match assembler.assemble() {
+        Ok(machine_code) => machine_code,
+        Err(errors) => {
+            let errors: Vec<Box<dyn Error>> = errors
+                .into_iter()
+                .map(|error| error.into())
+                .collect();
+
+            print_errors(input_path, &errors);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut simulator = Simulator::new(machine_code, inputs, seed);
+    if let Err(error) = simulator.run(MAX_RUN_STEPS) {
+        eprintln!("Failed to run \"{}\": {}", input_path, error);
+        return ExitCode::FAILURE;
+    }
+
+    println!("Halted at address {} after {} steps", simulator.program_counter - 1, simulator.steps);
+    for (register, value) in simulator.registers.iter().enumerate().skip(1) {
+        println!("r{}: {}", register, value);
+    }
+    println!("Zero: {}, Carry: {}", simulator.zero, simulator.carry);
+
+    let assertions = assembler.assertions();
+    let failed: Vec<_> = assertions
+        .iter()
+        .filter_map(|assertion| assertion.check(&simulator).err())
+        .collect();
+
+    for error in &failed {
+        eprintln!("{}", error);
+    }
+
+    if !failed.is_empty() {
+        eprintln!("{} of {} assertion{} failed", failed.len(), assertions.len(), if assertions.len() == 1 { "" } else { "s" });
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn diff_files(config: AssemblerConfig, values: &[&str], defines: &[(&str, &str)]) -> ExitCode {
+    if values.len() != 2 {
+        eprintln!("Expected old and new input files, got {} value(s)", values.len());
+        return ExitCode::FAILURE;
+    }
+
+    let mut programs = Vec::new();
+    for &input_path in values {
+        let source = match fs::read_to_string(input_path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("Failed to read \"{}\": {}", input_path, error);
+                return ExitCode::FAILURE;
+            }
+        };
+
+        let mut assembler = Assembler::new(config.clone());
+        if let Err(errors) = add_defines(&mut assembler, defines).and_then(|_| assembler.parse_with_overlay(input_path, &source)) {
+            print_errors(input_path, &errors);
+            return ExitCode::FAILURE;
+        }
+
+        let machine_code = match assembler.assemble() {
+            Ok(machine_code) => machine_code,
+            Err(errors) => {
+                let errors: Vec<Box<dyn Error>> = errors
+                    .into_iter()
+                    .map(|error| error.into())
+                    .collect();
+
+                print_errors(input_path, &errors);
+                return ExitCode::FAILURE;
+            }
+        };
+
+        programs.push((input_path, assembler, machine_code));
+    }
+
+    let (old, new) = (&programs[0], &programs[1]);
+    let length = old.2.len().max(new.2.len());
+
+    // Every changed address, with the source line it came from on each side
+    let describe = |program: &(&str, Assembler, Vec<Word>), address: usize| -> String {
+        let (path, assembler, machine_code) = program;
+
+        let word = match machine_code.get(address) {
+            Some(word) => format!("{:04X}", word),
+            None => return "(none)".to_string()
+        };
+
+        match assembler.source_text(address) {
+            Some((file, line, text)) => format!("{} {}:{}: {}", word, file.unwrap_or(*path), line, text.trim()),
+            None => format!("{} (inserted by the assembler)", word)
+        }
+    };
+
+    let mut changed = 0;
+    for address in 0..length {
+        if old.2.get(address) == new.2.get(address) {
+            continue;
+        }
+
+        changed += 1;
+        println!("Address {}:", address);
+        println!("  - {}", describe(old, address));
+        println!("  + {}", describe(new, address));
+    }
+
+    println!("{} of {} address{} changed", changed, length, if length == 1 { "" } else { "es" });
+    ExitCode::SUCCESS
+}
+
+// "-" reads the program from stdin
+fn parse_input(assembler: &mut Assembler, input_path: &str, defines: &[(&str, &str)]) -> Result<(), Vec<Box<dyn Error>>> {
+    add_defines(assembler, defines)?;
+
+    if input_path != "-" {
+        return assembler.parse_file(input_path);
+    }
+
+    let mut source = String::new();
+    if let Err(error) = io::stdin().read_to_string(&mut source) {
+        return Err(vec![error.into()]);
+    }
+
+    assembler.parse(&source)
+}
+
+fn add_defines(assembler: &mut Assembler, defines: &[(&str, &str)]) -> Result<(), Vec<Box<dyn Error>>> {
+    let errors: Vec<Box<dyn Error>> = defines
+        .iter()
+        .filter_map(|&(name, value)| assembler.add_define(name, value).err())
+        .map(|error| error.into())
+        .collect();
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(())
+}
+
+fn print_errors(input_path: &str, errors: &[Box<dyn Error>]) {
+    eprintln!("Failed to assemble \"{}\":", input_path);
+    for error in errors {
+        eprintln!("{}", error);
+    }
+    eprintln!("{} error{}", errors.len(), if errors.len() == 1 { "" } else { "s" });
 }
\ No newline at end of file
@@ -0,0 +1,51 @@
+use std::path::Path;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    Binary,
+    Text,
+    Hex,
+    Listing,
+    Report,
+    Container,
+    SExpr,
+    Logisim,
+    PlaygroundJson,
+    Mif
+}
+
+impl OutputFormat {
+    pub fn from_extension(path: &str) -> Option<Self> {
+        let extension = Path::new(path)
+            .extension()?
+            .to_str()?
+            .to_ascii_lowercase();
+
+        match extension.as_str() {
+            "bin" | "mc" => Some(Self::Binary),
+            "txt" => Some(Self::Text),
+            "hex" => Some(Self::Hex),
+            "lst" => Some(Self::Listing),
+            "out" => Some(Self::Report),
+            "bpuc" => Some(Self::Container),
+            "sexp" => Some(Self::SExpr),
+            "json" => Some(Self::PlaygroundJson),
+            "mif" => Some(Self::Mif),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_extension() {
+        assert_eq!(OutputFormat::from_extension("out.hex"), Some(OutputFormat::Hex));
+        assert_eq!(OutputFormat::from_extension("OUT.MC"), Some(OutputFormat::Binary));
+        assert_eq!(OutputFormat::from_extension("dir.lst/out.json"), Some(OutputFormat::PlaygroundJson));
+        assert_eq!(OutputFormat::from_extension("out.hx"), None);
+        assert_eq!(OutputFormat::from_extension("out"), None);
+    }
+}
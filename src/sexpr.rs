@@ -0,0 +1,51 @@
+use crate::encoding;
+use crate::encoding::Word;
+
+pub fn sexpr(word: Word) -> String {
+    let opcode = encoding::opcode(word);
+    let mnemonic = encoding::MNEMONICS[opcode as usize];
+
+    let reg_a = format!("(reg {})", encoding::register_a(word));
+    let reg_b = format!("(reg {})", encoding::register_b(word));
+    let reg_c = format!("(reg {})", encoding::register_c(word));
+    let address = format!("(addr {})", encoding::address(word));
+
+    let operands = match mnemonic {
+        "add" | "sub" | "nor" | "and" | "xor" => vec![reg_a, reg_b, reg_c],
+        "rsh" => vec![reg_a, reg_c],
+        "ldi" | "adi" => vec![reg_a, format!("(imm {})", encoding::immediate(word))],
+        "jmp" | "cal" => vec![address],
+        "brh" => vec![format!("(cond {})", encoding::CONDITIONS[encoding::condition(word) as usize]), address],
+        "lod" | "str" => vec![reg_a, reg_b, format!("(offset {})", encoding::offset(word))],
+        _ => Vec::new()
+    };
+
+    if operands.is_empty() {
+        return format!("({})", mnemonic);
+    }
+
+    format!("({} {})", mnemonic, operands.join(" "))
+}
+
+pub fn word_sexpr(word: Word) -> String {
+    format!("(word 0x{:04X})", word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instructions() {
+        assert_eq!(sexpr(0x2123), "(add (reg 1) (reg 2) (reg 3))");
+        assert_eq!(sexpr(0x8105), "(ldi (reg 1) (imm 5))");
+        assert_eq!(sexpr(0xB405), "(brh (cond notzero) (addr 5))");
+        assert_eq!(sexpr(0xE12F), "(lod (reg 1) (reg 2) (offset -1))");
+        assert_eq!(sexpr(0x1000), "(hlt)");
+    }
+
+    #[test]
+    fn data() {
+        assert_eq!(word_sexpr(0x1234), "(word 0x1234)");
+    }
+}
@@ -0,0 +1,307 @@
+use crate::assembler_error::AssemblerError;
+use crate::encoding;
+use crate::encoding::Word;
+use std::collections::VecDeque;
+
+pub const REGISTER_COUNT: usize = 16;
+pub const MEMORY_SIZE: usize = 256;
+pub const CALL_STACK_SIZE: usize = 16;
+
+// Used when no seed is given, so runs are always repeatable
+pub const DEFAULT_SEED: u32 = 0x2545_F491;
+
+// Memory from this address up is mapped to I/O ports, such as SCR_PIX_X (240)
+const PORTS_START: u8 = 240;
+const PORT_RNG: u8 = 254;
+const PORT_CONTROLLER: u8 = 255;
+
+pub struct Simulator {
+    pub registers: [u8; REGISTER_COUNT],
+    pub memory: [u8; MEMORY_SIZE],
+    pub zero: bool,
+    pub carry: bool,
+    pub program_counter: usize,
+    pub steps: u64,
+
+    machine_code: Vec<Word>,
+    call_stack: Vec<usize>,
+    inputs: VecDeque<u8>,
+    rng_state: u32,
+    halted: bool
+}
+
+impl Simulator {
+    // Reads from the controller port take the next value from inputs, or 0 once they run out
+    pub fn new(machine_code: Vec<Word>, inputs: Vec<u8>, seed: u32) -> Self {
+        Self {
+            registers: [0; REGISTER_COUNT],
+            memory: [0; MEMORY_SIZE],
+            zero: false,
+            carry: false,
+            program_counter: 0,
+            steps: 0,
+
+            machine_code,
+            call_stack: Vec::new(),
+            inputs: inputs.into(),
+            rng_state: seed,
+            halted: false
+        }
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn run(&mut self, max_steps: u64) -> Result<(), AssemblerError> {
+        while !self.halted {
+            if self.steps >= max_steps {
+                return Err(AssemblerError::new(format!("Program didn't halt within {} steps", max_steps)));
+            }
+
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn step(&mut self) -> Result<(), AssemblerError> {
+        if self.halted {
+            return Ok(());
+        }
+
+        let address = self.program_counter;
+        let word = match self.machine_code.get(address) {
+            Some(&word) => word,
+            None => return Err(AssemblerError::new(format!("Program counter ran past the end of the program at address {}", address)))
+        };
+
+        self.steps += 1;
+        self.program_counter = address + 1;
+
+        let a = self.registers[encoding::register_a(word) as usize];
+        let b = self.registers[encoding::register_b(word) as usize];
+        let reg_a = encoding::register_a(word) as usize;
+        let reg_b = encoding::register_b(word) as usize;
+        let reg_c = encoding::register_c(word) as usize;
+        let target = encoding::address(word) as usize;
+
+        match encoding::MNEMONICS[encoding::opcode(word) as usize] {
+            "nop" => {},
+            "hlt" => self.halted = true,
+            "add" => {
+                let result = self.add(a, b, false);
+                self.set_register(reg_c, result);
+            },
+            "sub" => {
+                // a - b is computed as a + !b + 1, so carry means there was no borrow
+                let result = self.add(a, !b, true);
+                self.set_register(reg_c, result);
+            },
+            "nor" => {
+                let result = self.logic(!(a | b));
+                self.set_register(reg_c, result);
+            },
+            "and" => {
+                let result = self.logic(a & b);
+                self.set_register(reg_c, result);
+            },
+            "xor" => {
+                let result = self.logic(a ^ b);
+                self.set_register(reg_c, result);
+            },
+            "rsh" => {
+                let result = self.logic(a >> 1);
+                self.set_register(reg_c, result);
+            },
+            "ldi" => self.set_register(reg_a, encoding::immediate(word) as u8),
+            "adi" => {
+                let result = self.add(a, encoding::immediate(word) as u8, false);
+                self.set_register(reg_a, result);
+            },
+            "jmp" => self.program_counter = target,
+            "brh" => {
+                let taken = match encoding::CONDITIONS[encoding::condition(word) as usize] {
+                    "zero" => self.zero,
+                    "notzero" => !self.zero,
+                    "carry" => self.carry,
+                    _ => !self.carry
+                };
+
+                if taken {
+                    self.program_counter = target;
+                }
+            },
+            "cal" => {
+                if self.call_stack.len() >= CALL_STACK_SIZE {
+                    return Err(AssemblerError::new(format!("Call stack overflow at address {}", address)));
+                }
+
+                self.call_stack.push(self.program_counter);
+                self.program_counter = target;
+            },
+            "ret" => {
+                match self.call_stack.pop() {
+                    Some(return_address) => self.program_counter = return_address,
+                    None => return Err(AssemblerError::new(format!("Return with an empty call stack at address {}", address)))
+                }
+            },
+            "lod" => {
+                let value = self.load(Self::memory_address(a, word));
+                self.set_register(reg_b, value);
+            },
+            _ => {
+                let memory_address = Self::memory_address(a, word);
+
+                // Output ports (screen, displays) aren't simulated
+                if memory_address < PORTS_START {
+                    self.memory[memory_address as usize] = b;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_register(&mut self, register: usize, value: u8) {
+        // r0 always reads as 0
+        if register != 0 {
+            self.registers[register] = value;
+        }
+    }
+
+    fn add(&mut self, a: u8, b: u8, carry_in: bool) -> u8 {
+        let sum = a as u16 + b as u16 + carry_in as u16;
+        let result = sum as u8;
+
+        self.zero = result == 0;
+        self.carry = sum > u8::MAX as u16;
+        result
+    }
+
+    fn logic(&mut self, result: u8) -> u8 {
+        self.zero = result == 0;
+        self.carry = false;
+        result
+    }
+
+    fn next_random(&mut self) -> u8 {
+        // Linear congruential generator, the high bits are the most random
+        self.rng_state = self.rng_state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (self.rng_state >> 24) as u8
+    }
+
+    fn memory_address(base: u8, word: Word) -> u8 {
+        base.wrapping_add(encoding::offset(word) as u8)
+    }
+
+    fn load(&mut self, memory_address: u8) -> u8 {
+        match memory_address {
+            PORT_RNG => self.next_random(),
+            PORT_CONTROLLER => self.inputs.pop_front().unwrap_or(0),
+            address if address >= PORTS_START => 0,
+            address => self.memory[address as usize]
+        }
+    }
+}
+
+// Checked once the program halts, written in source as "// @assert r1 == 5"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assertion {
+    pub register: usize,
+    pub equal: bool,
+    pub value: u8,
+    pub line: u32
+}
+
+impl Assertion {
+    pub fn check(&self, simulator: &Simulator) -> Result<(), AssemblerError> {
+        let actual = simulator.registers[self.register];
+
+        if (actual == self.value) != self.equal {
+            return Err(AssemblerError::new_line(format!(
+                "Assertion failed, r{} is {}, expected {}{}",
+                self.register,
+                actual,
+                if self.equal { "" } else { "anything but " },
+                self.value
+            ), self.line));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::assembler::Assembler;
+    use crate::assembler_config::AssemblerConfig;
+
+    fn simulator(source: &str, inputs: Vec<u8>, seed: u32) -> Simulator {
+        let mut assembler = Assembler::new(AssemblerConfig::default());
+        assembler.parse(source).unwrap();
+        Simulator::new(assembler.assemble().unwrap(), inputs, seed)
+    }
+
+    fn run(source: &str) -> Simulator {
+        let mut simulator = simulator(source, Vec::new(), DEFAULT_SEED);
+        simulator.run(1000).unwrap();
+        simulator
+    }
+
+    #[test]
+    fn loop_sum() {
+        let simulator = run("ldi r1 0\nldi r2 5\nloop:\nadd r1 r2 r1\ndec r2\nbrh ne loop\nhlt");
+        assert_eq!(simulator.registers[1], 15);
+        assert_eq!(simulator.registers[2], 0);
+        assert!(simulator.halted());
+    }
+
+    #[test]
+    fn call_and_return() {
+        let simulator = run("cal set\nhlt\nset:\nldi r3 7\nret");
+        assert_eq!(simulator.registers[3], 7);
+        assert_eq!(simulator.program_counter, 2);
+    }
+
+    #[test]
+    fn r0_stays_zero() {
+        assert_eq!(run("ldi r0 5\nhlt").registers[0], 0);
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut simulator = simulator("loop:\njmp loop", Vec::new(), DEFAULT_SEED);
+        assert_eq!(simulator.run(10).unwrap_err().description, "Program didn't halt within 10 steps");
+    }
+
+    #[test]
+    fn assertions() {
+        let mut assembler = Assembler::new(AssemblerConfig::default());
+        assembler.parse("ldi r1 5\nhlt // @assert r1 == 5\n// @assert r2 != 0").unwrap();
+
+        let mut simulator = Simulator::new(assembler.assemble().unwrap(), Vec::new(), DEFAULT_SEED);
+        simulator.run(1000).unwrap();
+
+        let assertions = assembler.assertions();
+        assert_eq!(assertions.len(), 2);
+        assert_eq!(assertions[0].check(&simulator), Ok(()));
+        assert_eq!(assertions[1].check(&simulator).unwrap_err().to_string(), "[Line 3] Assertion failed, r2 is 0, expected anything but 0");
+    }
+
+    #[test]
+    fn seeded_rng() {
+        let source = "ldi r1 254\nlod r1 r2 0\nlod r1 r3 0\nhlt";
+        let random = |seed: u32| {
+            let mut simulator = simulator(source, Vec::new(), seed);
+            simulator.run(1000).unwrap();
+            (simulator.registers[2], simulator.registers[3])
+        };
+
+        assert_eq!(random(42), random(42));
+        assert_eq!(random(1), (60, 94));
+        assert_ne!(random(1), random(DEFAULT_SEED));
+    }
+}
@@ -0,0 +1,179 @@
+use crate::encoding;
+use std::ops::Range;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Mnemonic,
+    Directive,
+    Label,
+    Register,
+    Immediate,
+    Condition,
+    // Reference to a label or define, such as "main" in "jmp main"
+    Name,
+    String,
+    Separator,
+    Comment
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    // Byte offsets into the whole source
+    pub span: Range<usize>
+}
+
+// Splits source into tokens line by line, without assembling it, such as for syntax highlighting.
+// Tokens are classified by their position and spelling alone, so a define used as a register is still a Name
+pub fn tokens(source: &str) -> impl Iterator<Item = Token> + '_ {
+    let mut offset = 0;
+
+    source.split_inclusive('\n').flat_map(move |line| {
+        let start = offset;
+        offset += line.len();
+
+        line_tokens(line, start)
+    })
+}
+
+fn line_tokens(line: &str, start: usize) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    let code = match line.find("//") {
+        Some(index) => {
+            let comment = line[index..].trim_end();
+            tokens.push(Token { kind: TokenKind::Comment, span: start + index..start + index + comment.len() });
+
+            &line[..index]
+        },
+        None => line
+    };
+
+    let mut code_tokens = Vec::new();
+
+    // The first word of every statement is a mnemonic, directive or label
+    let mut first = true;
+    let mut i = 0;
+
+    while let Some(char) = code[i..].chars().next() {
+        if char.is_whitespace() {
+            i += char.len_utf8();
+            continue;
+        }
+
+        if char == ';' {
+            code_tokens.push(Token { kind: TokenKind::Separator, span: start + i..start + i + 1 });
+
+            first = true;
+            i += 1;
+            continue;
+        }
+
+        let end = if char == '\'' || char == '"' {
+            literal_end(code, i, char)
+        } else {
+            code[i..]
+                .find(|c: char| c.is_whitespace() || c == ';')
+                .map_or(code.len(), |index| i + index)
+        };
+
+        let kind = classify(&code[i..end], first);
+        code_tokens.push(Token { kind, span: start + i..start + end });
+
+        first = kind == TokenKind::Label;
+        i = end;
+    }
+
+    // Comments come last on the line
+    code_tokens.append(&mut tokens);
+    code_tokens
+}
+
+// Index after the closing quote, skipping escaped quotes such as in '\''
+fn literal_end(code: &str, start: usize, quote: char) -> usize {
+    let mut escaped = false;
+
+    for (i, char) in code[start + 1..].char_indices() {
+        if escaped {
+            escaped = false;
+        } else if char == '\\' {
+            escaped = true;
+        } else if char == quote {
+            return start + 1 + i + 1;
+        }
+    }
+
+    code.len()
+}
+
+fn classify(word: &str, first: bool) -> TokenKind {
+    if first {
+        return if word.ends_with(':') {
+            TokenKind::Label
+        } else if word.starts_with('.') || word.starts_with('#') {
+            TokenKind::Directive
+        } else {
+            TokenKind::Mnemonic
+        };
+    }
+
+    if word.starts_with('"') {
+        return TokenKind::String;
+    }
+
+    let is_register = word
+        .strip_prefix('r')
+        .is_some_and(|index| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()));
+
+    if is_register {
+        TokenKind::Register
+    } else if word.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '\'' | '+' | '-' | '(')) {
+        TokenKind::Immediate
+    } else if encoding::condition_index(word).is_some() {
+        TokenKind::Condition
+    } else {
+        TokenKind::Name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds_and_spans(source: &str) -> Vec<(TokenKind, &str)> {
+        tokens(source).map(|token| (token.kind, &source[token.span])).collect()
+    }
+
+    #[test]
+    fn sample_line() {
+        let source = "loop: brh ne loop ; ldi r1 'A' // done\nhlt";
+
+        assert_eq!(kinds_and_spans(source), vec![
+            (TokenKind::Label, "loop:"),
+            (TokenKind::Mnemonic, "brh"),
+            (TokenKind::Condition, "ne"),
+            (TokenKind::Name, "loop"),
+            (TokenKind::Separator, ";"),
+            (TokenKind::Mnemonic, "ldi"),
+            (TokenKind::Register, "r1"),
+            (TokenKind::Immediate, "'A'"),
+            (TokenKind::Comment, "// done"),
+            (TokenKind::Mnemonic, "hlt")
+        ]);
+
+        let spans: Vec<Range<usize>> = tokens(source).map(|token| token.span).collect();
+        assert_eq!(spans[8], 31..38);
+        assert_eq!(spans[9], 39..42);
+    }
+
+    #[test]
+    fn literals_with_spaces() {
+        assert_eq!(kinds_and_spans(".string \"A B\"\nldi r1 ' '"), vec![
+            (TokenKind::Directive, ".string"),
+            (TokenKind::String, "\"A B\""),
+            (TokenKind::Mnemonic, "ldi"),
+            (TokenKind::Register, "r1"),
+            (TokenKind::Immediate, "' '")
+        ]);
+    }
+}